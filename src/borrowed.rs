@@ -0,0 +1,284 @@
+//! A cookie which borrows its strings from the header it was parsed from.
+
+use std::ascii::AsciiExt;
+use std::u64;
+
+use time;
+
+use {Cookie, Error, Priority, SameSite, RESERVED_ATTRS, parse_expires, strip_port};
+
+/// A `Cookie` whose name, value and attributes borrow from the parsed string
+///
+/// Created with `Cookie::parse_borrowed`, which avoids allocating a `String`
+/// for every field. Use `into_owned` to turn it into a `Cookie`.
+///
+/// # Example
+///
+/// ```
+/// use cookie::Cookie;
+///
+/// let header = String::from("session=abc; Path=/; Secure");
+/// let c = Cookie::parse_borrowed(&header).unwrap();
+/// assert_eq!(c.value, "abc");
+/// assert_eq!(c.path, Some("/"));
+/// assert_eq!(c.into_owned(), Cookie::parse(&header).unwrap());
+/// ```
+#[derive(PartialEq, Clone, Debug)]
+pub struct CookieRef<'a> {
+    #[allow(missing_docs)]
+    pub name: &'a str,
+    #[allow(missing_docs)]
+    pub value: &'a str,
+    #[allow(missing_docs)]
+    pub expires: Option<time::Tm>,
+    #[allow(missing_docs)]
+    pub max_age: Option<u64>,
+    /// The `Domain` attribute without any leading dot or port, but not yet
+    /// lowercased
+    pub domain: Option<&'a str>,
+    #[allow(missing_docs)]
+    pub path: Option<&'a str>,
+    #[allow(missing_docs)]
+    pub secure: bool,
+    #[allow(missing_docs)]
+    pub httponly: bool,
+    #[allow(missing_docs)]
+    pub same_site: Option<SameSite>,
+    #[allow(missing_docs)]
+    pub priority: Option<Priority>,
+    /// Unrecognized attributes with a value, in order
+    pub custom: Vec<(&'a str, &'a str)>,
+    /// Unrecognized attributes without a value, in order
+    pub flag_attrs: Vec<&'a str>,
+}
+
+impl<'a> CookieRef<'a> {
+    /// Converts this into an owned `Cookie`
+    ///
+    /// The result is the same as parsing the original string with
+    /// `Cookie::parse`.
+    pub fn into_owned(self) -> Cookie {
+        let mut c = Cookie::new(self.name.to_string(), self.value.to_string());
+        c.expires = self.expires;
+        c.max_age = self.max_age;
+        c.domain = self.domain.map(|d| d.to_ascii_lowercase());
+        c.path = self.path.map(|p| p.to_string());
+        c.secure = self.secure;
+        c.httponly = self.httponly;
+        c.same_site = self.same_site;
+        c.priority = self.priority;
+        for &(k, v) in self.custom.iter() {
+            c.custom.insert(k.to_string(), v.to_string());
+        }
+        c.flag_attrs = self.flag_attrs.iter().map(|f| f.to_string()).collect();
+        c
+    }
+}
+
+/// Parses `s`, keeping at most `max_attrs` attributes.
+pub fn parse(s: &str, max_attrs: usize) -> Result<CookieRef, Error> {
+    let mut pairs = s.split(';');
+    let keyval = match pairs.next() {
+        Some(s) => s,
+        _ => {
+            return Err(Error::MissingPair);
+        }
+    };
+    let (name, mut value) = try!(split(keyval));
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        value = &value[1..value.len() - 1];
+    }
+    if name.is_empty() {
+        return Err(Error::EmptyName);
+    }
+
+    // Control characters such as newlines would corrupt any header this
+    // cookie is later written into.
+    if name.chars().chain(value.chars()).any(|ch| ch.is_control()) {
+        return Err(Error::ControlCharacter);
+    }
+
+    let mut c = CookieRef {
+        name: name,
+        value: value,
+        expires: None,
+        max_age: None,
+        domain: None,
+        path: None,
+        secure: false,
+        httponly: false,
+        same_site: None,
+        priority: None,
+        custom: Vec::new(),
+        flag_attrs: Vec::new(),
+    };
+
+    for attr in pairs.take(max_attrs) {
+        let (k, v) = attr_split(attr);
+        match (known_attr(k), v) {
+            (Some("secure"), _) => c.secure = true,
+            (Some("httponly"), _) => c.httponly = true,
+            (Some("max-age"), Some(v)) => {
+                if let Some(max_age) = parse_max_age(v) {
+                    c.max_age = Some(max_age);
+                }
+            },
+            (Some("domain"), Some(v)) => {
+                if v.is_empty() {
+                    continue;
+                }
+
+                let domain = if v.starts_with('.') {
+                    &v[1..]
+                } else {
+                    v
+                };
+                c.domain = Some(strip_port(domain));
+            }
+            // An empty `Path` is the same as none at all, so the default
+            // path applies.
+            (Some("path"), Some(v)) => {
+                if !v.is_empty() {
+                    c.path = Some(v);
+                }
+            }
+            // Browsers ignore a `SameSite` or `Priority` they don't
+            // understand, so don't keep it around to be sent back out.
+            (Some("samesite"), v) => c.same_site = v.and_then(SameSite::parse),
+            (Some("priority"), v) => c.priority = v.and_then(Priority::parse),
+            (Some("expires"), Some(v)) => {
+                if let Some(expires) = parse_expires(v) {
+                    c.expires = Some(expires);
+                }
+            }
+            (_, Some(v)) => c.custom.push((k, v)),
+            (Some(_), None) => {}
+            (None, None) => {
+                if !k.is_empty() {
+                    c.flag_attrs.push(k);
+                }
+            }
+        }
+    }
+
+    return Ok(c);
+
+    // See RFC 6265 Section 5.2.2: the value is a string of digits, optionally
+    // preceded by a `-`. Negative values indicate that the earliest possible
+    // expiration time should be used, so set the max age as 0 seconds, and
+    // values too large for a `u64` are clamped rather than dropped.
+    fn parse_max_age(v: &str) -> Option<u64> {
+        let (negative, digits) = if v.starts_with('-') {
+            (true, &v[1..])
+        } else {
+            (false, v)
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None
+        }
+        if negative {
+            return Some(0)
+        }
+        Some(digits.parse().unwrap_or(u64::MAX))
+    }
+
+    fn known_attr(k: &str) -> Option<&'static str> {
+        RESERVED_ATTRS.iter().cloned().find(|name| k.eq_ignore_ascii_case(name))
+    }
+
+    fn attr_split<'a>(s: &'a str) -> (&'a str, Option<&'a str>) {
+        match s.find("=") {
+            Some(pos) => {
+                let parts = s.split_at(pos);
+                let value = parts.1[1..].trim();
+                (parts.0.trim(), Some(value))
+            }
+            None => (s.trim(), None)
+        }
+    }
+
+    fn split<'a>(s: &'a str) -> Result<(&'a str, &'a str), Error> {
+        macro_rules! try {
+            ($e:expr) => (match $e {
+                Some(s) => s,
+                None => return Err(Error::MissingPair)
+            })
+        }
+        let mut parts = s.trim().splitn(2, '=');
+        let first = try!(parts.next()).trim();
+        let second = try!(parts.next()).trim();
+        Ok((first, second))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use Cookie;
+
+    #[test]
+    fn parse_borrowed() {
+        let header = "foo = bar; Domain=.Example.COM:8080; Path=/a; Max-Age=-5; \
+                      Secure; SameSite=Strict; Flag; x=1; Expires=Sun, 23 Nov 2014 20:00:00 GMT";
+        let c = Cookie::parse_borrowed(header).unwrap();
+        assert_eq!(c.name, "foo");
+        assert_eq!(c.value, "bar");
+        assert_eq!(c.domain, Some("Example.COM"));
+        assert_eq!(c.path, Some("/a"));
+        assert_eq!(c.max_age, Some(0));
+        assert!(c.secure);
+        assert!(!c.httponly);
+        assert_eq!(c.custom, vec![("x", "1")]);
+        assert_eq!(c.flag_attrs, vec!["Flag"]);
+        assert!(c.expires.is_some());
+
+        let owned = c.into_owned();
+        assert_eq!(owned, Cookie::parse(header).unwrap());
+        assert_eq!(owned.domain, Some("example.com".to_string()));
+
+        assert!(Cookie::parse_borrowed("foo").is_err());
+        assert!(Cookie::parse_borrowed("=bar").is_err());
+    }
+
+    #[test]
+    fn empty_attributes() {
+        let c = Cookie::parse("foo=bar; Path=   ; Domain= ; Max-Age=; Expires=  ").unwrap();
+        assert_eq!(c.path, None);
+        assert_eq!(c.domain, None);
+        assert_eq!(c.max_age, None);
+        assert_eq!(c.expires, None);
+        assert!(c.is_simple_pair());
+        assert_eq!(c.to_string(), "foo=bar");
+
+        let c = Cookie::parse("foo=bar; Path=/a; Path=").unwrap();
+        assert_eq!(c.path, Some("/a".to_string()));
+        assert_eq!(Cookie::parse_borrowed("foo=bar; path=").unwrap().path, None);
+    }
+
+    #[test]
+    fn max_age() {
+        use std::u64;
+
+        fn max_age(v: &str) -> Option<u64> {
+            Cookie::parse_borrowed(&format!("foo=bar; Max-Age={}", v)).unwrap().max_age
+        }
+
+        assert_eq!(max_age("0"), Some(0));
+        assert_eq!(max_age("42"), Some(42));
+        assert_eq!(max_age("9223372036854775807"), Some(9223372036854775807));
+        assert_eq!(max_age("9223372036854775808"), Some(9223372036854775808));
+        assert_eq!(max_age("18446744073709551615"), Some(u64::MAX));
+        assert_eq!(max_age("18446744073709551616"), Some(u64::MAX));
+        assert_eq!(max_age("99999999999999999999"), Some(u64::MAX));
+        assert_eq!(max_age("-1"), Some(0));
+        assert_eq!(max_age("-99999999999999999999"), Some(0));
+        assert_eq!(max_age("abc"), None);
+        assert_eq!(max_age(""), None);
+        assert_eq!(max_age("-"), None);
+        assert_eq!(max_age("-abc"), None);
+        assert_eq!(max_age("+5"), None);
+        assert_eq!(max_age("5s"), None);
+
+        let c = Cookie::parse("foo=bar; Max-Age=5; Max-Age=abc").unwrap();
+        assert_eq!(c.max_age, Some(5));
+    }
+}
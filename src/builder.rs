@@ -0,0 +1,208 @@
+//! A builder for constructing cookies with attributes.
+
+use time;
+
+use Cookie;
+
+/// Structure used to construct a `Cookie` with a chain of method calls
+///
+/// Created with `Cookie::build`.
+///
+/// # Example
+///
+/// ```
+/// # extern crate cookie;
+/// # extern crate time;
+/// # fn main() {
+/// use cookie::Cookie;
+///
+/// let c = Cookie::build("session", "abc")
+///     .secure(true)
+///     .path("/")
+///     .expires_in(time::Duration::minutes(30))
+///     .finish();
+/// assert!(c.secure);
+/// assert_eq!(c.path, Some("/".to_string()));
+/// assert_eq!(c.max_age, Some(1800));
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CookieBuilder {
+    cookie: Cookie,
+    expires_in: Option<time::Duration>,
+}
+
+impl CookieBuilder {
+    /// Creates a new builder for a cookie with the given name and value
+    pub fn new<N, V>(name: N, value: V) -> CookieBuilder
+        where N: Into<String>, V: Into<String>
+    {
+        CookieBuilder {
+            cookie: Cookie::new(name.into(), value.into()),
+            expires_in: None,
+        }
+    }
+
+    /// Creates a new builder for a cookie which removes the cookie `name`
+    ///
+    /// The cookie has an empty value, a `max_age` of zero and expired at the
+    /// epoch. Browsers only remove a cookie when the removal's `Path` and
+    /// `Domain` match the stored cookie's, so set those before calling
+    /// `finish`.
+    pub fn removal<N>(name: N) -> CookieBuilder
+        where N: Into<String>
+    {
+        let mut builder = CookieBuilder::new(name, "");
+        builder.cookie.max_age = Some(0);
+        builder.cookie.expires = Some(time::at_utc(time::Timespec::new(0, 0)));
+        builder
+    }
+
+    /// Sets the `Path` attribute of the cookie
+    pub fn path<P: Into<String>>(mut self, path: P) -> CookieBuilder {
+        self.cookie.path = Some(path.into());
+        self
+    }
+
+    /// Sets the `Domain` attribute of the cookie
+    pub fn domain<D: Into<String>>(mut self, domain: D) -> CookieBuilder {
+        self.cookie.domain = Some(domain.into());
+        self
+    }
+
+    /// Sets whether the cookie has the `Secure` attribute
+    pub fn secure(mut self, secure: bool) -> CookieBuilder {
+        self.cookie.secure = secure;
+        self
+    }
+
+    /// Sets whether the cookie has the `HttpOnly` attribute
+    pub fn http_only(mut self, http_only: bool) -> CookieBuilder {
+        self.cookie.httponly = http_only;
+        self
+    }
+
+    /// Sets the `Max-Age` attribute of the cookie, in seconds
+    pub fn max_age(mut self, seconds: u64) -> CookieBuilder {
+        self.cookie.max_age = Some(seconds);
+        self
+    }
+
+    /// Sets the `Expires` attribute of the cookie
+    pub fn expires(mut self, when: time::Tm) -> CookieBuilder {
+        self.cookie.expires = Some(when);
+        self
+    }
+
+    /// Adds a custom attribute to the cookie, replacing any previous value
+    /// for `key`
+    pub fn custom<K, V>(mut self, key: K, value: V) -> CookieBuilder
+        where K: Into<String>, V: Into<String>
+    {
+        self.cookie.custom.insert(key.into(), value.into());
+        self
+    }
+
+    /// Makes the cookie expire once `duration` has passed
+    ///
+    /// When `finish` is called, both `expires` is set to the current time plus
+    /// `duration` and `max_age` is set to the number of seconds in `duration`.
+    /// Browsers which understand `Max-Age` prefer it, while older browsers
+    /// fall back to `Expires`. This overrides any `max_age` or `expires` set
+    /// on the builder.
+    pub fn expires_in(mut self, duration: time::Duration) -> CookieBuilder {
+        self.expires_in = Some(duration);
+        self
+    }
+
+    /// Finishes building and returns the `Cookie`
+    pub fn finish(self) -> Cookie {
+        let mut cookie = self.cookie;
+        if let Some(duration) = self.expires_in {
+            let secs = duration.num_seconds();
+            cookie.max_age = Some(if secs < 0 { 0 } else { secs as u64 });
+            cookie.expires = Some(time::now_utc() + duration);
+        }
+        cookie
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::{self, Duration};
+
+    use Cookie;
+
+    #[test]
+    fn build() {
+        let cookie = Cookie::build("foo", "bar".to_string()).finish();
+        assert_eq!(cookie, Cookie::new("foo".to_string(), "bar".to_string()));
+    }
+
+    #[test]
+    fn attributes() {
+        let expires = time::at_utc(time::Timespec::new(1416772800, 0));
+        let cookie = Cookie::build("session".to_string(), "abc")
+            .secure(true)
+            .http_only(true)
+            .path("/")
+            .domain("example.com")
+            .max_age(3600)
+            .expires(expires)
+            .custom("wut", "lol")
+            .custom("wut".to_string(), "rofl".to_string())
+            .finish();
+
+        let mut expected = Cookie::new("session".to_string(), "abc".to_string());
+        expected.secure = true;
+        expected.httponly = true;
+        expected.path = Some("/".to_string());
+        expected.domain = Some("example.com".to_string());
+        expected.max_age = Some(3600);
+        expected.expires = Some(expires);
+        expected.custom.insert("wut".to_string(), "rofl".to_string());
+        assert_eq!(cookie, expected);
+        assert_eq!(cookie.to_string(),
+                   "session=abc; HttpOnly; Secure; Path=/; Domain=example.com; \
+                    Max-Age=3600; Expires=Sun, 23 Nov 2014 20:00:00 GMT; wut=rofl");
+
+        let cookie = Cookie::build("foo", "bar").secure(true).secure(false).finish();
+        assert!(!cookie.secure);
+    }
+
+    #[test]
+    fn expires_in() {
+        let before = time::now_utc();
+        let cookie = Cookie::build("foo", "bar")
+            .expires_in(Duration::minutes(30))
+            .finish();
+        let after = time::now_utc();
+
+        assert_eq!(cookie.max_age, Some(1800));
+        let expires = cookie.expires.unwrap();
+        assert!(expires >= before + Duration::minutes(30));
+        assert!(expires <= after + Duration::minutes(30));
+
+        let cookie = Cookie::build("foo", "bar")
+            .expires_in(Duration::minutes(-5))
+            .finish();
+        assert_eq!(cookie.max_age, Some(0));
+        assert!(cookie.expires.unwrap() < time::now_utc());
+    }
+
+    #[test]
+    fn removal() {
+        let cookie = Cookie::build_removal("session")
+            .path("/admin")
+            .domain("example.com")
+            .finish();
+        assert_eq!(cookie.name, "session");
+        assert_eq!(cookie.value, "");
+        assert_eq!(cookie.max_age, Some(0));
+        assert_eq!(cookie.expires, Some(time::at_utc(time::Timespec::new(0, 0))));
+        assert_eq!(cookie.to_string(),
+                   "session=; Path=/admin; Domain=example.com; Max-Age=0; \
+                    Expires=Thu, 01 Jan 1970 00:00:00 GMT");
+        assert!(cookie.is_expired_at(time::now_utc()));
+    }
+}
@@ -0,0 +1,159 @@
+//! An ordered collection of a cookie's unrecognized attributes.
+
+use std::cmp::Ordering;
+use std::iter::FromIterator;
+use std::slice;
+
+/// The custom attributes of a `Cookie`, kept in the order they were added
+///
+/// This behaves much like a map from attribute name to value: inserting an
+/// attribute which is already present replaces its value in place. Unlike a
+/// `BTreeMap`, the attributes are emitted in insertion order unless they are
+/// reordered with `sort_by`.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+#[cfg_attr(feature = "serialize-rustc", derive(RustcEncodable, RustcDecodable))]
+pub struct CustomAttributes {
+    pairs: Vec<(String, String)>,
+}
+
+impl CustomAttributes {
+    /// Creates an empty set of custom attributes
+    pub fn new() -> CustomAttributes {
+        CustomAttributes { pairs: Vec::new() }
+    }
+
+    /// Sets the value of an attribute, returning its previous value
+    ///
+    /// A new attribute is added at the end, while an existing one keeps its
+    /// position.
+    pub fn insert(&mut self, key: String, value: String) -> Option<String> {
+        for pair in self.pairs.iter_mut() {
+            if pair.0 == key {
+                return Some(::std::mem::replace(&mut pair.1, value))
+            }
+        }
+        self.pairs.push((key, value));
+        None
+    }
+
+    /// Returns the value of an attribute
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.pairs.iter().find(|pair| pair.0 == key).map(|pair| &pair.1)
+    }
+
+    /// Returns whether an attribute is present
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Removes an attribute, returning its value
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        match self.pairs.iter().position(|pair| pair.0 == key) {
+            Some(i) => Some(self.pairs.remove(i).1),
+            None => None,
+        }
+    }
+
+    /// Returns the number of attributes
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// Returns whether there are no attributes
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Removes all attributes
+    pub fn clear(&mut self) {
+        self.pairs.clear();
+    }
+
+    /// Returns an iterator over the attributes and their values, in order
+    pub fn iter<'a>(&'a self) -> Iter<'a> {
+        Iter { inner: self.pairs.iter() }
+    }
+
+    /// Sorts the attributes by name with the given comparison function
+    ///
+    /// The sort is stable, so attributes which compare equal keep their
+    /// relative order.
+    pub fn sort_by<F>(&mut self, mut cmp: F)
+        where F: FnMut(&str, &str) -> Ordering
+    {
+        self.pairs.sort_by(|a, b| cmp(&a.0, &b.0));
+    }
+}
+
+impl FromIterator<(String, String)> for CustomAttributes {
+    fn from_iter<I>(iter: I) -> CustomAttributes
+        where I: IntoIterator<Item = (String, String)>
+    {
+        let mut attrs = CustomAttributes::new();
+        for (k, v) in iter {
+            attrs.insert(k, v);
+        }
+        attrs
+    }
+}
+
+impl<'a> IntoIterator for &'a CustomAttributes {
+    type Item = (&'a String, &'a String);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// Iterator over the custom attributes of a cookie
+pub struct Iter<'a> {
+    inner: slice::Iter<'a, (String, String)>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a String, &'a String);
+
+    fn next(&mut self) -> Option<(&'a String, &'a String)> {
+        self.inner.next().map(|pair| (&pair.0, &pair.1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CustomAttributes;
+
+    #[test]
+    fn insertion_order() {
+        let mut attrs = CustomAttributes::new();
+        assert!(attrs.is_empty());
+        assert_eq!(attrs.insert("b".to_string(), "1".to_string()), None);
+        assert_eq!(attrs.insert("a".to_string(), "2".to_string()), None);
+        assert_eq!(attrs.insert("c".to_string(), "3".to_string()), None);
+        assert_eq!(attrs.insert("a".to_string(), "4".to_string()), Some("2".to_string()));
+
+        let keys = attrs.iter().map(|(k, _)| &k[..]).collect::<Vec<_>>();
+        assert_eq!(keys, ["b", "a", "c"]);
+        assert_eq!(attrs.get("a"), Some(&"4".to_string()));
+        assert_eq!(attrs.len(), 3);
+
+        assert_eq!(attrs.remove("b"), Some("1".to_string()));
+        assert_eq!(attrs.remove("b"), None);
+        assert!(!attrs.contains_key("b"));
+        assert_eq!(attrs.len(), 2);
+    }
+
+    #[test]
+    fn sort_by() {
+        let mut attrs = vec![("b", "1"), ("a", "2"), ("c", "3")].into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<CustomAttributes>();
+        attrs.sort_by(|a, b| a.cmp(b));
+        let keys = attrs.iter().map(|(k, _)| &k[..]).collect::<Vec<_>>();
+        assert_eq!(keys, ["a", "b", "c"]);
+    }
+}
@@ -11,12 +11,13 @@
 //! cookies, etc. This functionality can also be chained together.
 
 
-use std::collections::{HashMap, HashSet};
+use std::ascii::AsciiExt;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::cell::RefCell;
 use std::fmt;
 use time;
 
-use Cookie;
+use {Cookie, Error};
 
 /// A jar of cookies for managing a session
 ///
@@ -39,9 +40,24 @@ pub struct CookieJar<'a> {
     flavor: Flavor<'a>,
 }
 
+/// How `CookieJar::merge_from` resolves a cookie present in both jars
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum MergeStrategy {
+    /// Keep the cookie already in the jar being merged into.
+    PreferSelf,
+    /// Replace it with the cookie from the other jar.
+    PreferOther,
+    /// Keep whichever cookie expires later, preferring the cookie already in
+    /// the jar on a tie. A persistent cookie is considered newer than a
+    /// session cookie.
+    PreferNewer,
+}
+
 enum Flavor<'a> {
     Child(Child<'a>),
     Root(Root),
+    // A root jar whose state is owned elsewhere, by a `SyncCookieJar`.
+    Shared(&'a Root),
 }
 
 struct Child<'a> {
@@ -52,6 +68,7 @@ struct Child<'a> {
 
 type Read = fn(&Root, Cookie) -> Option<Cookie>;
 type Write = fn(&Root, Cookie) -> Cookie;
+type Predicate = Box<Fn(&Cookie) -> bool + Send>;
 
 #[cfg(feature = "secure")]
 type SigningKey = Vec<u8>;
@@ -74,11 +91,145 @@ fn prepare_key(_key: &[u8]) -> () {
     ()
 }
 
-struct Root {
+// The state of a root jar. Everything in it is `Send`, so a `SyncCookieJar`
+// can keep it behind a `Mutex`.
+pub struct Root {
     map: RefCell<HashMap<String, Cookie>>,
     new_cookies: RefCell<HashSet<String>>,
-    removed_cookies: RefCell<HashSet<String>>,
+    removed_cookies: RefCell<HashMap<String, Cookie>>,
+    defaults: RefCell<Vec<(Predicate, Cookie)>>,
+    clock: Box<Fn() -> time::Tm + Send>,
     _key: SigningKey,
+    _old_keys: Vec<SigningKey>,
+}
+
+impl Root {
+    fn removal(&self, name: String) -> Cookie {
+        let mut c = Cookie::new(name, String::new());
+        c.max_age = Some(0);
+        let mut now = (self.clock)();
+        now.tm_year -= 1;
+        c.expires = Some(now);
+        c
+    }
+
+    // A removal which also copies the attributes browsers need to match
+    // before deleting the stored cookie.
+    fn removal_of(&self, cookie: &Cookie) -> Cookie {
+        let mut removal = self.removal(cookie.name.clone());
+        removal.path = cookie.path.clone();
+        removal.domain = cookie.domain.clone();
+        removal.secure = cookie.secure;
+        removal
+    }
+
+    // Reads `cookie` with the current key, falling back to each old key in
+    // turn.
+    #[cfg(feature = "secure")]
+    fn read_with_keys(&self, cookie: Cookie, read: fn(&[u8], Cookie) -> Option<Cookie>)
+                      -> Option<Cookie> {
+        if let Some(cookie) = read(&self._key, cookie.clone()) {
+            return Some(cookie)
+        }
+        self._old_keys.iter().filter_map(|key| read(key, cookie.clone())).next()
+    }
+
+    fn apply_defaults(&self, cookie: &mut Cookie) {
+        for &(ref pred, ref template) in self.defaults.borrow().iter() {
+            if pred(cookie) {
+                apply_default(cookie, template);
+            }
+        }
+    }
+}
+
+// Fills in every attribute `cookie` lacks from `template`. The template's name
+// and value are ignored.
+fn apply_default(cookie: &mut Cookie, template: &Cookie) {
+    if cookie.expires.is_none() {
+        cookie.expires = template.expires;
+    }
+    if cookie.max_age.is_none() {
+        cookie.max_age = template.max_age;
+    }
+    if cookie.domain.is_none() {
+        cookie.domain = template.domain.clone();
+    }
+    if cookie.path.is_none() {
+        cookie.path = template.path.clone();
+    }
+    cookie.secure = cookie.secure || template.secure;
+    cookie.httponly = cookie.httponly || template.httponly;
+    if cookie.same_site.is_none() {
+        cookie.same_site = template.same_site;
+    }
+    if cookie.priority.is_none() {
+        cookie.priority = template.priority;
+    }
+    for (k, v) in template.custom.iter() {
+        if !cookie.custom.contains_key(k) {
+            cookie.custom.insert(k.clone(), v.clone());
+        }
+    }
+}
+
+fn enforce_prefix(cookie: &mut Cookie) -> Result<(), Error> {
+    if cookie.name.starts_with("__Host-") {
+        if cookie.domain.is_some() {
+            return Err(Error::PrefixMismatch)
+        }
+        match cookie.path {
+            Some(ref path) if path != "/" => return Err(Error::PrefixMismatch),
+            _ => {}
+        }
+        cookie.path = Some("/".to_string());
+        cookie.secure = true;
+    } else if cookie.name.starts_with("__Secure-") {
+        cookie.secure = true;
+    }
+    Ok(())
+}
+
+// Takes the state out of a root jar, for a `SyncCookieJar` to own.
+pub fn into_root(jar: CookieJar<'static>) -> Root {
+    match jar.flavor {
+        Flavor::Root(root) => root,
+        _ => unreachable!(),
+    }
+}
+
+// Runs `f` with a root jar backed by `root`.
+pub fn with_root<F, R>(root: &Root, f: F) -> R
+    where F: FnOnce(&mut CookieJar) -> R
+{
+    f(&mut CookieJar { flavor: Flavor::Shared(root) })
+}
+
+/// A set of changes to apply to a cookie jar at once
+///
+/// Created by `CookieJar::batch`.
+pub struct BatchContext {
+    ops: Vec<BatchOp>,
+}
+
+enum BatchOp {
+    Add(Cookie),
+    Remove(String),
+}
+
+impl BatchContext {
+    /// Queues a cookie to be added to the jar.
+    ///
+    /// The cookie is added with `CookieJar::add` once the batch is applied,
+    /// so its name prefix is checked then.
+    pub fn add(&mut self, cookie: Cookie) {
+        self.ops.push(BatchOp::Add(cookie));
+    }
+
+    /// Queues a cookie to be removed from the jar.
+    pub fn remove(&mut self, name: &str) {
+        self.ops.push(BatchOp::Remove(name.to_string()));
+    }
 }
 
 /// Iterator over the cookies in a cookie jar
@@ -92,22 +243,102 @@ impl<'a> CookieJar<'a> {
     ///
     /// The given key is used to sign cookies in the signed cookie jar.
     pub fn new(key: &[u8]) -> CookieJar<'static> {
+        CookieJar::with_clock(key, Box::new(time::now_utc))
+    }
+
+    /// Creates a new empty cookie jar with the given signing key, which reads
+    /// the current time from `clock`.
+    ///
+    /// Every time-dependent operation on the jar and its children, such as
+    /// `remove_expired` or `permanent`, uses `clock` instead of the system
+    /// time. This is primarily useful to test expiry deterministically.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate cookie;
+    /// # extern crate time;
+    /// # fn main() {
+    /// use cookie::CookieJar;
+    ///
+    /// let epoch = time::at_utc(time::Timespec::new(0, 0));
+    /// let c = CookieJar::with_clock(b"f8f9eaf1ecdedff5e5b749c58115441e",
+    ///                               Box::new(move || epoch));
+    /// c.remove_expired();
+    /// # }
+    /// ```
+    pub fn with_clock(key: &[u8], clock: Box<Fn() -> time::Tm + Send>)
+                      -> CookieJar<'static> {
         CookieJar {
             flavor: Flavor::Root(Root {
                 map: RefCell::new(HashMap::new()),
                 new_cookies: RefCell::new(HashSet::new()),
-                removed_cookies: RefCell::new(HashSet::new()),
+                removed_cookies: RefCell::new(HashMap::new()),
+                defaults: RefCell::new(Vec::new()),
+                clock: clock,
                 _key: prepare_key(key),
+                _old_keys: Vec::new(),
             })
         }
     }
 
+    /// Creates a new empty cookie jar which signs and encrypts with `key`,
+    /// but also accepts cookies signed or encrypted with any of `old_keys`.
+    ///
+    /// This allows a key to be rotated without invalidating the cookies
+    /// clients already hold: cookies are always written with `key`, while
+    /// the signed and encrypted child jars read a cookie with `key` first and
+    /// then with each old key in order. Remove an old key once every cookie
+    /// written with it has expired.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use cookie::CookieJar;
+    /// let c = CookieJar::with_keys(b"2f8f9eaf1ecdedff5e5b749c58115441",
+    ///                              &[b"f8f9eaf1ecdedff5e5b749c58115441e"]);
+    /// # drop(c);
+    /// ```
+    pub fn with_keys(key: &[u8], old_keys: &[&[u8]]) -> CookieJar<'static> {
+        let mut jar = CookieJar::new(key);
+        if let Flavor::Root(ref mut root) = jar.flavor {
+            root._old_keys = old_keys.iter().map(|key| prepare_key(key)).collect();
+        }
+        jar
+    }
+
+    /// Creates a new cookie jar with the given signing key, holding the
+    /// cookies of a `Cookie` request header.
+    ///
+    /// Each `name=value` pair in `header` is added as an original cookie, so
+    /// none of them appear in the jar's `delta`. The header is parsed with
+    /// `Cookie::parse_request_header`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use cookie::CookieJar;
+    /// let c = CookieJar::from_request_header(b"f8f9eaf1ecdedff5e5b749c58115441e",
+    ///                                        "a=1; b=2").unwrap();
+    /// assert_eq!(c.find("b").unwrap().value, "2");
+    /// assert!(c.delta().is_empty());
+    /// ```
+    pub fn from_request_header(key: &[u8], header: &str)
+                               -> Result<CookieJar<'static>, Error> {
+        let mut jar = CookieJar::new(key);
+        for cookie in try!(Cookie::parse_request_header(header)) {
+            jar.add_original(cookie);
+        }
+        Ok(jar)
+    }
+
     fn root<'b>(&'b self) -> &'b Root {
         let mut cur = self;
         loop {
             match cur.flavor {
                 Flavor::Child(ref child) => cur = child.parent,
                 Flavor::Root(ref me) => return me,
+                Flavor::Shared(me) => return me,
             }
         }
     }
@@ -120,9 +351,9 @@ impl<'a> CookieJar<'a> {
     pub fn add_original(&mut self, cookie: Cookie) {
         match self.flavor {
             Flavor::Child(..) => panic!("can't add an original cookie to a child jar!"),
-            Flavor::Root(ref mut root) => {
+            Flavor::Root(..) | Flavor::Shared(..) => {
                 let name = cookie.name.clone();
-                root.map.borrow_mut().insert(name, cookie);
+                self.root().map.borrow_mut().insert(name, cookie);
             }
         }
     }
@@ -131,42 +362,364 @@ impl<'a> CookieJar<'a> {
     ///
     /// If this jar is a child cookie jar, this will walk up the chain of
     /// borrowed jars, modifying the cookie as it goes along.
-    pub fn add(&self, mut cookie: Cookie) {
-        let mut cur = self;
+    ///
+    /// Cookies named with a `__Secure-` or `__Host-` prefix automatically have
+    /// the attributes required by that prefix set, see `try_add`. A cookie
+    /// whose `Domain` or `Path` conflicts with its prefix would be rejected
+    /// by browsers, so it isn't added at all; use `try_add` to be told.
+    pub fn add(&self, cookie: Cookie) {
+        let _ = self.try_add(cookie);
+    }
+
+    /// Adds a new cookie to this cookie jar, enforcing cookie name prefixes.
+    ///
+    /// Browsers drop cookies whose names start with `__Secure-` unless they
+    /// are `Secure`, and cookies whose names start with `__Host-` unless they
+    /// are also `Path=/` and have no `Domain`. These attributes are filled in
+    /// when missing, and an error is returned if the cookie asks for a
+    /// conflicting `Path` or `Domain` instead.
+    pub fn try_add(&self, mut cookie: Cookie) -> Result<(), Error> {
         let root = self.root();
+        root.apply_defaults(&mut cookie);
+        try!(enforce_prefix(&mut cookie));
+        let mut cur = self;
         loop {
             match cur.flavor {
                 Flavor::Child(ref child) => {
                     cookie = (child.write)(root, cookie);
                     cur = child.parent;
                 }
-                Flavor::Root(..) => break,
+                Flavor::Root(..) | Flavor::Shared(..) => break,
             }
         }
         let name = cookie.name.clone();
         root.map.borrow_mut().insert(name.clone(), cookie);
         root.removed_cookies.borrow_mut().remove(&name);
         root.new_cookies.borrow_mut().insert(name);
+        Ok(())
+    }
+
+    /// Sets default attributes for every cookie later added to this jar.
+    ///
+    /// This is `set_conditional_default` with a predicate which matches every
+    /// cookie.
+    pub fn set_default(&self, template: Cookie) {
+        self.set_conditional_default(|_| true, template);
+    }
+
+    /// Sets default attributes for cookies later added to this jar for which
+    /// `pred` returns `true`.
+    ///
+    /// When such a cookie is added, each attribute it doesn't set is copied
+    /// from `template`: `Secure` and `HttpOnly` are turned on if the template
+    /// has them, and custom attributes are added unless the cookie already
+    /// has one with the same name. The template's name and value are ignored.
+    /// Defaults apply to additions through any child jar, before name
+    /// prefixes are enforced, in the order they were set. Cookies already in
+    /// the jar and original cookies are left alone.
+    ///
+    /// `pred` must be `Send` so that a jar can be shared between threads by a
+    /// `SyncCookieJar`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::{Cookie, CookieJar};
+    ///
+    /// let c = CookieJar::new(b"f8f9eaf1ecdedff5e5b749c58115441e");
+    /// c.set_conditional_default(|c| c.name.starts_with("__Host-"),
+    ///                           Cookie::parse("t=; Path=/; HttpOnly").unwrap());
+    /// c.add(Cookie::new("__Host-id".to_string(), "1".to_string()));
+    /// c.add(Cookie::new("theme".to_string(), "dark".to_string()));
+    /// assert!(c.find("__Host-id").unwrap().httponly);
+    /// assert!(!c.find("theme").unwrap().httponly);
+    /// ```
+    pub fn set_conditional_default<F>(&self, pred: F, template: Cookie)
+        where F: Fn(&Cookie) -> bool + Send + 'static
+    {
+        let root = self.root();
+        root.defaults.borrow_mut().push((Box::new(pred), template));
     }
 
     /// Removes a cookie from this cookie jar.
     pub fn remove(&self, cookie: &str) {
         let root = self.root();
-        let cookie = cookie.to_string();
-        root.map.borrow_mut().remove(&cookie);
-        root.new_cookies.borrow_mut().remove(&cookie);
-        root.removed_cookies.borrow_mut().insert(cookie);
+        let name = cookie.to_string();
+        let removed = root.map.borrow_mut().remove(&name).unwrap_or_else(|| {
+            Cookie::new(name.clone(), String::new())
+        });
+        root.new_cookies.borrow_mut().remove(&name);
+        root.removed_cookies.borrow_mut().insert(name, removed);
+    }
+
+    /// Applies a batch of additions and removals to this cookie jar at once.
+    ///
+    /// Changes queued on the `BatchContext` passed to `f` are only applied
+    /// after `f` returns, by replaying them through `add` and `remove` in the
+    /// order they were queued, so the jar is unchanged while `f` runs. As
+    /// with any sequence of `add` and `remove` calls, the delta only records
+    /// the last change to each cookie: adding then removing a cookie yields a
+    /// single removal.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::{Cookie, CookieJar};
+    ///
+    /// let c = CookieJar::new(b"f8f9eaf1ecdedff5e5b749c58115441e");
+    /// c.batch(|b| {
+    ///     b.add(Cookie::new("a".to_string(), "1".to_string()));
+    ///     b.add(Cookie::new("b".to_string(), "2".to_string()));
+    ///     b.remove("a");
+    /// });
+    /// assert!(c.find("a").is_none());
+    /// assert_eq!(c.find("b").unwrap().value, "2");
+    /// ```
+    pub fn batch<F: FnOnce(&mut BatchContext)>(&self, f: F) {
+        let mut batch = BatchContext { ops: Vec::new() };
+        f(&mut batch);
+        for op in batch.ops {
+            match op {
+                BatchOp::Add(cookie) => self.add(cookie),
+                BatchOp::Remove(name) => self.remove(&name),
+            }
+        }
+    }
+
+    /// Renews the lifetime of a cookie in this cookie jar.
+    ///
+    /// The cookie's `max_age` is set to `new_max_age`, and if it has an
+    /// `expires` date that is moved to `new_max_age` from now. The renewed
+    /// cookie is included in the `delta` of this jar. Nothing happens if the
+    /// cookie isn't in the jar.
+    pub fn renew(&self, name: &str, new_max_age: time::Duration) {
+        let root = self.root();
+        let mut map = root.map.borrow_mut();
+        let cookie = match map.get_mut(name) {
+            Some(cookie) => cookie,
+            None => return,
+        };
+        let secs = new_max_age.num_seconds();
+        cookie.max_age = Some(if secs < 0 { 0 } else { secs as u64 });
+        if cookie.expires.is_some() {
+            cookie.expires = Some((root.clock)() + new_max_age);
+        }
+        root.new_cookies.borrow_mut().insert(name.to_string());
+    }
+
+    /// Marks a cookie in this cookie jar as changed.
+    ///
+    /// The cookie is included in the next `delta` even though it wasn't
+    /// modified through the jar, for example an original cookie which must be
+    /// sent back to the client. Nothing happens if the cookie isn't in the jar.
+    pub fn touch(&self, name: &str) {
+        let root = self.root();
+        if root.map.borrow().contains_key(name) {
+            root.new_cookies.borrow_mut().insert(name.to_string());
+        }
+    }
+
+    /// Copies every cookie from `other` into this cookie jar.
+    ///
+    /// Cookies are stored by name, so a cookie in `other` collides with a
+    /// cookie in this jar of the same name, and `strategy` decides which one
+    /// is kept. Every cookie taken from `other` is recorded in the delta.
+    ///
+    /// Cookies are copied as stored in the root jars, so signed or encrypted
+    /// cookies stay readable only if both jars share a key. Expiry for
+    /// `PreferNewer` is computed with `Cookie::effective_expiry` using this
+    /// jar's clock.
+    pub fn merge_from(&self, other: &CookieJar, strategy: MergeStrategy) {
+        let root = self.root();
+        let now = (root.clock)();
+        let incoming: Vec<Cookie> = other.root().map.borrow().values()
+            .cloned()
+            .collect();
+        let mut map = root.map.borrow_mut();
+        for cookie in incoming {
+            let take = match map.get(&cookie.name) {
+                None => true,
+                Some(existing) => match strategy {
+                    MergeStrategy::PreferSelf => false,
+                    MergeStrategy::PreferOther => true,
+                    MergeStrategy::PreferNewer => {
+                        cookie.effective_expiry(now) > existing.effective_expiry(now)
+                    }
+                },
+            };
+            if take {
+                let name = cookie.name.clone();
+                map.insert(name.clone(), cookie);
+                root.removed_cookies.borrow_mut().remove(&name);
+                root.new_cookies.borrow_mut().insert(name);
+            }
+        }
+    }
+
+    /// Removes every cookie which has expired from this cookie jar.
+    ///
+    /// Expiry is checked with `Cookie::is_expired_at` against the jar's
+    /// clock, and each expired cookie is removed as if by `remove`.
+    pub fn remove_expired(&self) {
+        let root = self.root();
+        let now = (root.clock)();
+        let expired: Vec<String> = root.map.borrow().values()
+            .filter(|c| c.is_expired_at(now))
+            .map(|c| c.name.clone())
+            .collect();
+        for name in expired {
+            self.remove(&name);
+        }
+    }
+
+    /// Returns a copy of every cookie in this jar which has expired at `now`.
+    ///
+    /// This uses the same check as `remove_expired` but leaves the jar
+    /// untouched, so the cookies about to be removed can be inspected or
+    /// logged first. The cookies are sorted by name.
+    pub fn expired_cookies(&self, now: time::Tm) -> Vec<Cookie> {
+        let root = self.root();
+        let mut expired: Vec<Cookie> = root.map.borrow().values()
+            .filter(|c| c.is_expired_at(now))
+            .cloned()
+            .collect();
+        expired.sort_by(|a, b| a.name.cmp(&b.name));
+        expired
+    }
+
+    /// Counts the live cookies in this jar for each domain.
+    ///
+    /// Domains are lowercased and stripped of any leading dot, and host-only
+    /// cookies, which have no `Domain`, are counted under the empty string.
+    /// Cookies which have expired according to the jar's clock aren't
+    /// counted.
+    pub fn counts_by_domain(&self) -> BTreeMap<String, usize> {
+        let root = self.root();
+        let now = (root.clock)();
+        let mut counts = BTreeMap::new();
+        for cookie in root.map.borrow().values() {
+            if cookie.is_expired_at(now) {
+                continue
+            }
+            let domain = match cookie.domain {
+                Some(ref d) => d.trim_left_matches('.').to_ascii_lowercase(),
+                None => String::new(),
+            };
+            *counts.entry(domain).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Returns the total length in bytes of the `Set-Cookie` header values of
+    /// the live cookies in this jar.
+    ///
+    /// Each cookie is measured with `Cookie::header_len` as it is stored in
+    /// the root jar, so signed or encrypted values count at their full size.
+    /// Cookies which have expired according to the jar's clock aren't
+    /// counted.
+    pub fn total_bytes(&self) -> usize {
+        let root = self.root();
+        let now = (root.clock)();
+        let map = root.map.borrow();
+        map.values()
+           .filter(|c| !c.is_expired_at(now))
+           .map(|c| c.header_len())
+           .fold(0, |a, b| a + b)
+    }
+
+    /// Computes a hash of the live cookies in this jar.
+    ///
+    /// Every cookie which hasn't expired according to the jar's clock is
+    /// hashed as its `Set-Cookie` header value, in sorted order, so jars
+    /// holding the same cookies hash the same no matter the order they were
+    /// added in. The hash is 64-bit FNV-1a, so it is the same across builds
+    /// and versions of Rust and is suitable as a cache key, such as an
+    /// `ETag`.
+    pub fn state_hash(&self) -> u64 {
+        let root = self.root();
+        let now = (root.clock)();
+        let mut cookies: Vec<String> = root.map.borrow().values()
+            .filter(|c| !c.is_expired_at(now))
+            .map(|c| c.to_string())
+            .collect();
+        cookies.sort();
+        // Each header is followed by a NUL byte, which no valid header holds,
+        // so `a=1` then `b=2` doesn't hash the same as `a=1b=2`.
+        let mut hash = 0xcbf29ce484222325u64;
+        for cookie in &cookies {
+            for &byte in cookie.as_bytes().iter().chain(&[0]) {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
+    }
+
+    /// Returns the earliest time at which a cookie in this jar expires.
+    ///
+    /// Each cookie's expiry is computed by `Cookie::effective_expiry` using
+    /// the jar's clock. Returns `None` if the jar only holds session cookies,
+    /// which is useful for scheduling the next call to `remove_expired`.
+    pub fn next_expiry(&self) -> Option<time::Tm> {
+        let root = self.root();
+        let now = (root.clock)();
+        let map = root.map.borrow();
+        map.values().filter_map(|c| c.effective_expiry(now)).min()
     }
 
     /// Clears all cookies from this cookie jar.
     pub fn clear(&self) {
         let root = self.root();
-        let all_cookies: Vec<_> = root.map.borrow().keys().map(|n| n.to_owned()).collect();
-        root.map.borrow_mut().clear();
+        let all_cookies: Vec<_> = root.map.borrow_mut().drain().collect();
         root.new_cookies.borrow_mut().clear();
         root.removed_cookies.borrow_mut().extend(all_cookies);
     }
 
+    /// Removes every cookie scoped to `domain` from this cookie jar.
+    ///
+    /// This removes each cookie whose `Domain` is `domain` or one of its
+    /// subdomains, compared case-insensitively. Cookies scoped to a parent
+    /// domain, which other hosts share, are kept. Each is removed as if by
+    /// `remove`, so the delta holds a removal with the cookie's path and
+    /// domain for each of them.
+    ///
+    /// A host-only cookie doesn't record the host which set it, so pass that
+    /// host as `host` if every host-only cookie in the jar belongs to it.
+    /// Host-only cookies are then also removed if `host` is `domain` or one
+    /// of its subdomains, and are otherwise always kept.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::{Cookie, CookieJar};
+    ///
+    /// let mut c = CookieJar::new(b"f8f9eaf1ecdedff5e5b749c58115441e");
+    /// c.add_original(Cookie::parse("a=1; Domain=example.com").unwrap());
+    /// c.add_original(Cookie::parse("b=1").unwrap());
+    /// c.clear_domain("example.com", Some("www.example.com"));
+    /// assert_eq!(c.delta().len(), 2);
+    /// ```
+    pub fn clear_domain(&self, domain: &str, host: Option<&str>) {
+        let root = self.root();
+        let domain = domain.trim_left_matches('.').to_ascii_lowercase();
+        let subdomain = format!(".{}", domain);
+        let within = |d: &str| {
+            let d = d.trim_left_matches('.').to_ascii_lowercase();
+            d == domain || d.ends_with(&subdomain)
+        };
+        let clear_host_only = host.map_or(false, |h| within(h));
+        let matching: Vec<String> = root.map.borrow().values()
+            .filter(|c| match c.domain {
+                Some(ref d) => within(d),
+                None => clear_host_only,
+            })
+            .map(|c| c.name.clone())
+            .collect();
+        for name in matching {
+            self.remove(&name);
+        }
+    }
+
     /// Finds a cookie inside of this cookie jar.
     ///
     /// The cookie is subject to modification by any of the child cookie jars
@@ -174,17 +727,56 @@ impl<'a> CookieJar<'a> {
     pub fn find(&self, name: &str) -> Option<Cookie> {
         let name = name.to_string();
         let root = self.root();
-        if root.removed_cookies.borrow().contains(&name) {
+        if root.removed_cookies.borrow().contains_key(&name) {
             return None
         }
         root.map.borrow().get(&name).and_then(|c| self.try_read(root, c.clone()))
     }
 
+    /// Returns the cookie named `name`, if this jar holds one.
+    ///
+    /// This is the same as `find`. Names are compared exactly, so `get("a")`
+    /// never returns a cookie named `A`. As with `find`, a child jar only
+    /// returns a cookie which it can read, so `get` on a signed jar verifies
+    /// the signature and returns the original value.
+    ///
+    /// The cookies are kept behind a `RefCell` so that child jars can modify
+    /// them, which means a reference into the jar can't be handed out and a
+    /// copy of the cookie is returned instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::{Cookie, CookieJar};
+    ///
+    /// let c = CookieJar::new(b"f8f9eaf1ecdedff5e5b749c58115441e");
+    /// c.add(Cookie::new("key".to_string(), "value".to_string()));
+    /// assert_eq!(c.get("key").unwrap().value, "value");
+    /// assert!(c.get("Key").is_none());
+    /// ```
+    pub fn get(&self, name: &str) -> Option<Cookie> {
+        self.find(name)
+    }
+
+    /// Returns whether this jar holds a cookie named `name`.
+    ///
+    /// This is `get(name).is_some()`, so on a child jar only cookies which
+    /// it can read count.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
     /// Creates a child signed cookie jar.
     ///
     /// All cookies read from the child jar will require a valid signature and
     /// all cookies written will be signed automatically.
     ///
+    /// The signature is a base64-encoded HMAC-SHA256 of the cookie's
+    /// `name=value` pair, keyed with this jar's key, and is prepended to the
+    /// value. Reading a cookie which wasn't signed, or whose name or value was
+    /// altered, fails and the cookie isn't found. Attributes such as `Path`
+    /// aren't signed, since browsers never send them back.
+    ///
     /// # Example
     ///
     /// ```rust
@@ -209,18 +801,47 @@ impl<'a> CookieJar<'a> {
         };
 
         fn design(root: &Root, cookie: Cookie) -> Option<Cookie> {
-            secure::design(&root._key, cookie)
+            root.read_with_keys(cookie, secure::design)
         }
         fn sign(root: &Root, cookie: Cookie) -> Cookie {
             secure::sign(&root._key, cookie)
         }
     }
 
+    /// Verifies a signed cookie value as read by the signed child jar.
+    ///
+    /// Returns the value without its signature if `value` was signed by this
+    /// jar's key, or one of its old keys, for a cookie named exactly `name`.
+    /// A value signed for any other name is rejected, so a valid signed value
+    /// can't be replayed under a different cookie name.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use cookie::{Cookie, CookieJar};
+    /// let c = CookieJar::new(b"f8f9eaf1ecdedff5e5b749c58115441e");
+    /// c.signed().add(Cookie::new("a".to_string(), "value".to_string()));
+    /// let signed = c.find("a").unwrap().value;
+    ///
+    /// assert_eq!(c.verify_named("a", &signed), Some("value".to_string()));
+    /// assert_eq!(c.verify_named("b", &signed), None);
+    /// ```
+    #[cfg(feature = "secure")]
+    pub fn verify_named(&self, name: &str, value: &str) -> Option<String> {
+        let cookie = Cookie::new(name.to_string(), value.to_string());
+        self.root().read_with_keys(cookie, secure::design).map(|c| c.value)
+    }
+
     /// Creates a child encrypted cookie jar.
     ///
-    /// All cookies read from the child jar must be encrypted and signed by a
-    /// valid key and all cookies written will be encrypted and signed
-    /// automatically.
+    /// All cookies read from the child jar must be encrypted and authenticated
+    /// by a valid key and all cookies written will be encrypted and
+    /// authenticated automatically.
+    ///
+    /// Values are encrypted with AES-256-GCM using a random nonce for every
+    /// write, which is stored in the value alongside the ciphertext. The
+    /// cookie's name is authenticated along with the value, so a cookie whose
+    /// name, value or nonce was tampered with isn't found.
     ///
     /// # Example
     ///
@@ -246,10 +867,10 @@ impl<'a> CookieJar<'a> {
             })
         };
         fn read(root: &Root, cookie: Cookie) -> Option<Cookie> {
-            secure::design_and_decrypt(&root._key, cookie)
+            root.read_with_keys(cookie, secure::decrypt)
         }
         fn write(root: &Root, cookie: Cookie) -> Cookie {
-            secure::encrypt_and_sign(&root._key, cookie)
+            secure::encrypt(&root._key, cookie)
         }
     }
 
@@ -270,10 +891,10 @@ impl<'a> CookieJar<'a> {
             Some(cookie)
         }
 
-        fn write(_root: &Root, mut cookie: Cookie) -> Cookie {
+        fn write(root: &Root, mut cookie: Cookie) -> Cookie {
             // Expire 20 years in the future
             cookie.max_age = Some(3600 * 24 * 365 * 20);
-            let mut now = time::now();
+            let mut now = (root.clock)();
             now.tm_year += 20;
             cookie.expires = Some(now);
             cookie
@@ -282,24 +903,59 @@ impl<'a> CookieJar<'a> {
 
     /// Calculates the changes that have occurred to this cookie jar over time,
     /// returning a vector of `Set-Cookie` headers.
+    ///
+    /// Each cookie removed from the jar is represented by a removal cookie,
+    /// with an empty value and an expiry in the past, which copies the
+    /// `Path`, `Domain` and `Secure` attributes of the removed cookie as
+    /// `deletion_for` does. Removals come first, followed by every cookie
+    /// added or changed, each sorted by name.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::{Cookie, CookieJar};
+    ///
+    /// let mut c = CookieJar::new(b"f8f9eaf1ecdedff5e5b749c58115441e");
+    /// c.add_original(Cookie::parse("old=1; Path=/app").unwrap());
+    /// c.remove("old");
+    /// c.add(Cookie::new("new".to_string(), "2".to_string()));
+    ///
+    /// let headers = c.delta().iter().map(|c| c.to_string()).collect::<Vec<_>>();
+    /// assert!(headers[0].starts_with("old=; Path=/app; Max-Age=0; Expires="));
+    /// assert_eq!(headers[1], "new=2");
+    /// ```
     pub fn delta(&self) -> Vec<Cookie> {
         let mut ret = Vec::new();
         let root = self.root();
-        for cookie in root.removed_cookies.borrow().iter() {
-            let mut c = Cookie::new(cookie.clone(), String::new());
-            c.max_age = Some(0);
-            let mut now = time::now();
-            now.tm_year -= 1;
-            c.expires = Some(now);
-            ret.push(c);
+        let removed = root.removed_cookies.borrow();
+        let mut names: Vec<&String> = removed.keys().collect();
+        names.sort();
+        for name in names {
+            ret.push(root.removal_of(&removed[name]));
         }
         let map = root.map.borrow();
-        for cookie in root.new_cookies.borrow().iter() {
-            ret.push(map.get(cookie).unwrap().clone());
+        let new_cookies = root.new_cookies.borrow();
+        let mut names: Vec<&String> = new_cookies.iter().collect();
+        names.sort();
+        for name in names {
+            ret.push(map.get(name).unwrap().clone());
         }
         return ret;
     }
 
+    /// Creates a cookie which deletes a cookie stored in this jar.
+    ///
+    /// The removal cookie has an empty value and an expiry in the past, and
+    /// copies the `Path`, `Domain` and `Secure` attributes of the stored
+    /// cookie, since browsers only delete a cookie whose path and domain
+    /// match the removal exactly. Returns `None` if the cookie isn't in the
+    /// jar.
+    pub fn deletion_for(&self, name: &str) -> Option<Cookie> {
+        let root = self.root();
+        let map = root.map.borrow();
+        map.get(name).map(|cookie| root.removal_of(cookie))
+    }
+
     fn try_read(&self, root: &Root, mut cookie: Cookie) -> Option<Cookie> {
         let mut jar = self;
         loop {
@@ -310,7 +966,7 @@ impl<'a> CookieJar<'a> {
                     };
                     jar = parent;
                 }
-                Flavor::Root(..) => return Some(cookie),
+                Flavor::Root(..) | Flavor::Shared(..) => return Some(cookie),
             }
         }
     }
@@ -321,9 +977,27 @@ impl<'a> CookieJar<'a> {
     /// this is an encrypted child jar then only valid encrypted cookies will be
     /// yielded. If the root cookie jar is iterated over then all cookies will
     /// be yielded.
+    ///
+    /// Cookies are yielded in order of their names, so iterating over two
+    /// jars holding the same cookies gives the same sequence.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::{Cookie, CookieJar};
+    ///
+    /// let c = CookieJar::new(b"f8f9eaf1ecdedff5e5b749c58115441e");
+    /// c.add(Cookie::new("b".to_string(), "2".to_string()));
+    /// c.add(Cookie::new("a".to_string(), "1".to_string()));
+    /// let names = c.iter().map(|c| c.name).collect::<Vec<_>>();
+    /// assert_eq!(names, ["a", "b"]);
+    /// ```
     pub fn iter(&self) -> Iter {
         let map = self.root().map.borrow();
-        Iter { jar: self, keys: map.keys().cloned().collect() }
+        let mut keys: Vec<String> = map.keys().cloned().collect();
+        // `next` pops from the back, so sort in reverse.
+        keys.sort_by(|a, b| b.cmp(a));
+        Iter { jar: self, keys: keys }
     }
 }
 
@@ -381,112 +1055,92 @@ mod secure {
 
     pub const MIN_KEY_LEN: usize = 32;
 
-    // If a SHA1 HMAC is good enough for rails, it's probably good enough
-    // for us as well:
-    //
-    // https://github.com/rails/rails/blob/master/activesupport/lib
-    //                   /active_support/message_verifier.rb#L70
+    // The base64 encoding of a SHA256 HMAC.
+    const SIGNATURE_LEN: usize = 44;
+
+    // The signature covers the `name=value` pair, which is all a browser
+    // sends back, so a signed value can't be moved to another cookie name.
     pub fn sign(key: &[u8], mut cookie: Cookie) -> Cookie {
-        let signature = dosign(key, &cookie.to_string());
-        cookie.value.push_str("--");
-        cookie.value.push_str(&signature.to_base64(STANDARD));
+        let signature = dosign(key, &cookie.name, &cookie.value);
+        let mut value = signature.to_base64(STANDARD);
+        value.push_str(&cookie.value);
+        cookie.value = value;
         cookie
     }
 
-    fn split_value(val: &str) -> Option<(&str, Vec<u8>)> {
-        let parts = val.split("--");
-        let ext = match parts.last() {
-            Some(ext) => ext,
-            _ => return None,
-        };
-        let val_len = val.len();
-        if ext.len() == val_len { return None }
-        let text = &val[..val_len - ext.len() - 2];
-        let ext = match ext.from_base64() {
-            Ok(sig) => sig, Err(..) => return None,
-        };
-
-        Some((text, ext))
-    }
-
     pub fn design(key: &[u8], mut cookie: Cookie) -> Option<Cookie> {
-        let signed_value = cookie.value;
-        let (text, signature) = match split_value(&signed_value) {
-            Some(pair) => pair, None => return None
+        if !cookie.value.is_char_boundary(SIGNATURE_LEN) {
+            return None
+        }
+        let value = cookie.value.split_off(SIGNATURE_LEN);
+        let signature = match cookie.value.from_base64() {
+            Ok(sig) => sig, Err(..) => return None,
         };
-        cookie.value = text.to_owned();
 
-        let expected = dosign(key, &cookie.to_string());
+        let expected = dosign(key, &cookie.name, &value);
         if expected.len() != signature.len() ||
            !memcmp::eq(&expected, &signature) {
             return None
         }
+        cookie.value = value;
         Some(cookie)
     }
 
-    fn dosign(key: &[u8], val: &str) -> Vec<u8> {
+    fn dosign(key: &[u8], name: &str, value: &str) -> Vec<u8> {
         let pkey = PKey::hmac(key).unwrap();
-        let mut signer = Signer::new(MessageDigest::sha1(), &pkey).unwrap();
-        signer.update(val.as_bytes()).unwrap();
+        let mut signer = Signer::new(MessageDigest::sha256(), &pkey).unwrap();
+        signer.update(name.as_bytes()).unwrap();
+        signer.update(b"=").unwrap();
+        signer.update(value.as_bytes()).unwrap();
         signer.finish().unwrap()
     }
 
-    // Implementation details were taken from Rails. See
-    // https://github.com/rails/rails/blob/master/activesupport/lib/active_support/message_encryptor.rb#L57
-    pub fn encrypt_and_sign(key: &[u8], mut cookie: Cookie) -> Cookie {
-        let encrypted_data = encrypt_data(key, &cookie.value);
-        cookie.value = encrypted_data;
-        sign(key, cookie)
-    }
-
-    fn encrypt_data(key: &[u8], val: &str) -> String {
-        let iv = random_iv();
-        let iv_str = iv.to_base64(STANDARD);
-
-        let mut encrypted_data = symm::encrypt(symm::Cipher::aes_256_cbc(),
-                                               &key[..MIN_KEY_LEN],
-                                               Some(&iv),
-                                               val.as_bytes()).unwrap()
-                                                              .to_base64(STANDARD);
-
-        encrypted_data.push_str("--");
-        encrypted_data.push_str(&iv_str);
-        encrypted_data
+    // AES-256-GCM, with a fresh random nonce for each cookie. The nonce and
+    // authentication tag are stored around the ciphertext, and the cookie's
+    // name is authenticated as additional data so that a value can't be moved
+    // to another cookie name.
+    const NONCE_LEN: usize = 12;
+    const TAG_LEN: usize = 16;
+
+    pub fn encrypt(key: &[u8], mut cookie: Cookie) -> Cookie {
+        let mut nonce = vec![0; NONCE_LEN];
+        openssl::rand::rand_bytes(&mut nonce).unwrap();
+        let mut tag = vec![0; TAG_LEN];
+        let ciphertext = symm::encrypt_aead(symm::Cipher::aes_256_gcm(),
+                                            &key[..MIN_KEY_LEN],
+                                            Some(&nonce),
+                                            cookie.name.as_bytes(),
+                                            cookie.value.as_bytes(),
+                                            &mut tag).unwrap();
+
+        let mut data = nonce;
+        data.extend_from_slice(&ciphertext);
+        data.extend_from_slice(&tag);
+        cookie.value = data.to_base64(STANDARD);
+        cookie
     }
 
-    pub fn design_and_decrypt(key: &[u8], cookie: Cookie) -> Option<Cookie> {
-        let mut cookie = match design(key, cookie) {
-            Some(cookie) => cookie,
-            None => return None
+    pub fn decrypt(key: &[u8], mut cookie: Cookie) -> Option<Cookie> {
+        let data = match cookie.value.from_base64() {
+            Ok(data) => data, Err(..) => return None,
         };
-
-        let decrypted_data = decrypt_data(key, &cookie.value)
-                                .and_then(|data| String::from_utf8(data).ok());
-        match decrypted_data {
-            Some(val) => { cookie.value = val; Some(cookie) }
-            None => None
+        if data.len() < NONCE_LEN + TAG_LEN {
+            return None
         }
-    }
-
-    fn decrypt_data(key: &[u8], val: &str) -> Option<Vec<u8>> {
-        let (val, iv) = match split_value(val) {
-            Some(pair) => pair, None => return None
+        let (nonce, rest) = data.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        let plaintext = match symm::decrypt_aead(symm::Cipher::aes_256_gcm(),
+                                                 &key[..MIN_KEY_LEN],
+                                                 Some(nonce),
+                                                 cookie.name.as_bytes(),
+                                                 ciphertext,
+                                                 tag) {
+            Ok(plaintext) => plaintext, Err(..) => return None,
         };
-
-        let actual = match val.from_base64() {
-            Ok(actual) => actual, Err(_) => return None
-        };
-
-        Some(symm::decrypt(symm::Cipher::aes_256_cbc(),
-                           &key[..MIN_KEY_LEN],
-                           Some(&iv),
-                           &actual).unwrap())
-    }
-
-    fn random_iv() -> Vec<u8> {
-        let mut ret = vec![0; 16];
-        openssl::rand::rand_bytes(&mut ret).unwrap();
-        return ret
+        match String::from_utf8(plaintext) {
+            Ok(value) => { cookie.value = value; Some(cookie) }
+            Err(..) => None,
+        }
     }
 
     pub fn prepare_key(key: &[u8]) -> Vec<u8> {
@@ -496,7 +1150,8 @@ mod secure {
 
 #[cfg(test)]
 mod test {
-    use {Cookie, CookieJar};
+    use {Cookie, CookieJar, MergeStrategy};
+    use time::{self, Duration};
 
     const KEY: &'static [u8] = b"f8f9eaf1ecdedff5e5b749c58115441e";
 
@@ -543,9 +1198,12 @@ mod test {
             $c.$secure().add(Cookie::new("test".to_string(), "test".to_string()));
             assert!($c.$secure().find("test").unwrap().value == "test");
             let mut cookie = $c.find("test").unwrap();
-            cookie.max_age = Some(cookie.max_age.unwrap_or(0) + 1);
+            cookie.name = "other".to_string();
             $c.add(cookie);
-            assert!($c.$secure().find("test").is_none());
+            assert!($c.$secure().find("other").is_none());
+
+            $c.add(Cookie::new("plain".to_string(), "test".to_string()));
+            assert!($c.$secure().find("plain").is_none());
         })
     }
 
@@ -560,7 +1218,11 @@ mod test {
     #[test]
     fn encrypted() {
         let c = CookieJar::new(KEY);
-        secure_behaviour!(c, encrypted)
+        secure_behaviour!(c, encrypted);
+
+        c.encrypted().add(Cookie::new("a".to_string(), "same".to_string()));
+        c.encrypted().add(Cookie::new("b".to_string(), "same".to_string()));
+        assert!(c.find("a").unwrap().value != c.find("b").unwrap().value);
     }
 
     #[test]
@@ -576,6 +1238,613 @@ mod test {
         assert!(cookie.max_age.is_some());
     }
 
+    #[test]
+    fn renew() {
+        let mut c = CookieJar::new(KEY);
+
+        let mut cookie = Cookie::new("session".to_string(), "1".to_string());
+        cookie.max_age = Some(60);
+        cookie.expires = Some(time::now_utc());
+        c.add_original(cookie);
+        c.add_original(Cookie::new("plain".to_string(), "1".to_string()));
+        assert!(c.delta().is_empty());
+
+        c.renew("session", Duration::hours(1));
+        c.renew("plain", Duration::minutes(5));
+        c.renew("missing", Duration::minutes(5));
+        assert!(c.find("missing").is_none());
+
+        let cookie = c.find("session").unwrap();
+        assert_eq!(cookie.max_age, Some(3600));
+        assert!(cookie.expires.unwrap() > time::now_utc() + Duration::minutes(59));
+        let cookie = c.find("plain").unwrap();
+        assert_eq!(cookie.max_age, Some(300));
+        assert!(cookie.expires.is_none());
+
+        let delta = c.delta();
+        assert_eq!(delta.len(), 2);
+        assert_eq!(delta[0].name, "plain");
+        assert_eq!(delta[1].name, "session");
+        assert_eq!(delta[1].max_age, Some(3600));
+    }
+
+    #[test]
+    fn clock() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicIsize, Ordering};
+
+        let epoch = time::at_utc(time::Timespec::new(0, 0));
+        let offset = Arc::new(AtomicIsize::new(0));
+        let clock_offset = offset.clone();
+        let c = CookieJar::with_clock(KEY, Box::new(move || {
+            epoch + Duration::seconds(clock_offset.load(Ordering::SeqCst) as i64)
+        }));
+
+        let mut soon = Cookie::new("soon".to_string(), "1".to_string());
+        soon.expires = Some(epoch + Duration::seconds(10));
+        let mut later = Cookie::new("later".to_string(), "1".to_string());
+        later.expires = Some(epoch + Duration::seconds(20));
+        let mut deleted = Cookie::new("deleted".to_string(), "1".to_string());
+        deleted.max_age = Some(0);
+        c.add(soon);
+        c.add(later);
+        c.add(deleted);
+        c.add(Cookie::new("session".to_string(), "1".to_string()));
+
+        c.remove_expired();
+        assert!(c.find("deleted").is_none());
+        assert!(c.find("soon").is_some());
+
+        offset.store(10, Ordering::SeqCst);
+        c.remove_expired();
+        assert!(c.find("soon").is_none());
+        assert!(c.find("later").is_some());
+
+        offset.store(30, Ordering::SeqCst);
+        c.remove_expired();
+        assert!(c.find("later").is_none());
+        assert!(c.find("session").is_some());
+
+        let mut removed = c.delta().into_iter()
+            .filter(|c| c.max_age == Some(0))
+            .map(|c| c.name)
+            .collect::<Vec<_>>();
+        removed.sort();
+        assert_eq!(removed, ["deleted", "later", "soon"]);
+
+        c.permanent().add(Cookie::new("permanent".to_string(), "1".to_string()));
+        let expires = c.find("permanent").unwrap().expires.unwrap();
+        assert_eq!(expires.tm_year, 90);
+    }
+
+    #[test]
+    fn next_expiry() {
+        let epoch = time::at_utc(time::Timespec::new(0, 0));
+        let c = CookieJar::with_clock(KEY, Box::new(move || epoch));
+        assert!(c.next_expiry().is_none());
+
+        c.add(Cookie::new("session".to_string(), "1".to_string()));
+        assert!(c.next_expiry().is_none());
+
+        let mut later = Cookie::new("later".to_string(), "1".to_string());
+        later.expires = Some(epoch + Duration::hours(2));
+        c.add(later);
+        assert_eq!(c.next_expiry(), Some(epoch + Duration::hours(2)));
+
+        let mut soon = Cookie::new("soon".to_string(), "1".to_string());
+        soon.max_age = Some(3600);
+        c.add(soon);
+        assert_eq!(c.next_expiry(), Some(epoch + Duration::hours(1)));
+
+        c.remove("soon");
+        assert_eq!(c.next_expiry(), Some(epoch + Duration::hours(2)));
+    }
+
+    #[test]
+    fn expired_cookies() {
+        let epoch = time::at_utc(time::Timespec::new(0, 0));
+        let c = CookieJar::with_clock(KEY, Box::new(move || epoch));
+        c.add(Cookie::new("session".to_string(), "1".to_string()));
+
+        let mut deleted = Cookie::new("deleted".to_string(), "1".to_string());
+        deleted.max_age = Some(0);
+        let mut old = Cookie::new("old".to_string(), "1".to_string());
+        old.expires = Some(epoch - Duration::hours(1));
+        let mut live = Cookie::new("live".to_string(), "1".to_string());
+        live.expires = Some(epoch + Duration::hours(1));
+        c.add(deleted.clone());
+        c.add(old.clone());
+        c.add(live);
+
+        assert_eq!(c.expired_cookies(epoch), vec![deleted, old]);
+        assert_eq!(c.iter().count(), 4);
+        let names = c.expired_cookies(epoch + Duration::hours(2)).into_iter()
+            .map(|c| c.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["deleted", "live", "old"]);
+    }
+
+    #[test]
+    fn merge_from() {
+        let epoch = time::at_utc(time::Timespec::new(0, 0));
+        let jar = |value: &str, expires: Option<i64>| {
+            let mut c = CookieJar::with_clock(KEY, Box::new(move || epoch));
+            let mut cookie = Cookie::new("shared".to_string(), value.to_string());
+            cookie.path = Some("/".to_string());
+            cookie.domain = Some("example.com".to_string());
+            cookie.expires = expires.map(|h| epoch + Duration::hours(h));
+            c.add_original(cookie);
+            c
+        };
+        let value = |c: &CookieJar| c.find("shared").unwrap().value;
+
+        let mine = jar("mine", Some(1));
+        let theirs = jar("theirs", Some(2));
+        theirs.add(Cookie::new("extra".to_string(), "1".to_string()));
+
+        mine.merge_from(&theirs, MergeStrategy::PreferSelf);
+        assert_eq!(value(&mine), "mine");
+        assert_eq!(mine.find("extra").unwrap().value, "1");
+        let delta = mine.delta();
+        assert_eq!(delta.len(), 1);
+        assert_eq!(delta[0].name, "extra");
+
+        let mine = jar("mine", Some(1));
+        mine.merge_from(&theirs, MergeStrategy::PreferOther);
+        assert_eq!(value(&mine), "theirs");
+        assert_eq!(mine.delta().len(), 2);
+
+        let mine = jar("mine", Some(1));
+        mine.merge_from(&theirs, MergeStrategy::PreferNewer);
+        assert_eq!(value(&mine), "theirs");
+
+        let mine = jar("mine", Some(3));
+        mine.merge_from(&theirs, MergeStrategy::PreferNewer);
+        assert_eq!(value(&mine), "mine");
+
+        let mine = jar("mine", None);
+        mine.merge_from(&theirs, MergeStrategy::PreferNewer);
+        assert_eq!(value(&mine), "theirs");
+
+        let mine = jar("mine", Some(2));
+        mine.merge_from(&theirs, MergeStrategy::PreferNewer);
+        assert_eq!(value(&mine), "mine");
+        assert!(mine.delta().iter().all(|c| c.name != "shared"));
+    }
+
+    #[test]
+    fn counts_by_domain() {
+        let c = CookieJar::new(KEY);
+        assert!(c.counts_by_domain().is_empty());
+
+        for &(name, domain) in [("a", Some("example.com")),
+                                ("b", Some(".Example.com")),
+                                ("c", Some("example.org")),
+                                ("d", None)].iter() {
+            let mut cookie = Cookie::new(name.to_string(), "1".to_string());
+            cookie.domain = domain.map(|d| d.to_string());
+            c.add(cookie);
+        }
+        let mut expired = Cookie::new("e".to_string(), "1".to_string());
+        expired.domain = Some("example.org".to_string());
+        expired.max_age = Some(0);
+        c.add(expired);
+
+        let counts = c.counts_by_domain();
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts["example.com"], 2);
+        assert_eq!(counts["example.org"], 1);
+        assert_eq!(counts[""], 1);
+    }
+
+    #[test]
+    fn batch() {
+        let c = CookieJar::new(KEY);
+        c.add(Cookie::new("kept".to_string(), "1".to_string()));
+
+        c.batch(|b| {
+            b.add(Cookie::new("temp".to_string(), "1".to_string()));
+            b.remove("temp");
+            assert!(c.find("kept").is_some());
+            b.remove("kept");
+            assert!(c.find("kept").is_some());
+            b.add(Cookie::new("kept".to_string(), "2".to_string()));
+        });
+
+        assert!(c.find("temp").is_none());
+        assert_eq!(c.find("kept").unwrap().value, "2");
+        let delta = c.delta();
+        assert_eq!(delta.len(), 2);
+        assert_eq!(delta[0].name, "temp");
+        assert_eq!(delta[0].max_age, Some(0));
+        assert_eq!(delta[1].name, "kept");
+        assert_eq!(delta[1].value, "2");
+    }
+
+    #[test]
+    fn total_bytes() {
+        let c = CookieJar::new(KEY);
+        assert_eq!(c.total_bytes(), 0);
+
+        let a = Cookie::parse("a=1; Path=/").unwrap();
+        let b = Cookie::parse("session=0123456789; HttpOnly; Secure").unwrap();
+        c.add(a.clone());
+        c.add(b.clone());
+        assert_eq!(c.total_bytes(), a.header_len() + b.header_len());
+        assert_eq!(c.total_bytes(), 11 + 36);
+
+        c.add(Cookie::parse("gone=1; Max-Age=0").unwrap());
+        assert_eq!(c.total_bytes(), a.header_len() + b.header_len());
+    }
+
+    #[test]
+    fn state_hash() {
+        let a = CookieJar::new(KEY);
+        let b = CookieJar::new(KEY);
+        assert_eq!(a.state_hash(), b.state_hash());
+
+        a.add(Cookie::new("x".to_string(), "1".to_string()));
+        a.add(Cookie::new("y".to_string(), "2".to_string()));
+        b.add(Cookie::new("y".to_string(), "2".to_string()));
+        assert!(a.state_hash() != b.state_hash());
+        b.add(Cookie::new("x".to_string(), "1".to_string()));
+        assert_eq!(a.state_hash(), b.state_hash());
+        assert_eq!(a.state_hash(), 0xb1b4ce4f542b8aff);
+        assert_eq!(CookieJar::new(KEY).state_hash(), 0xcbf29ce484222325);
+
+        let mut expired = Cookie::new("z".to_string(), "3".to_string());
+        expired.max_age = Some(0);
+        b.add(expired);
+        assert_eq!(a.state_hash(), b.state_hash());
+
+        b.add(Cookie::new("x".to_string(), "changed".to_string()));
+        assert!(a.state_hash() != b.state_hash());
+    }
+
+    #[test]
+    fn touch() {
+        let mut c = CookieJar::new(KEY);
+
+        let mut original = Cookie::new("original".to_string(), "1".to_string());
+        original.path = Some("/".to_string());
+        c.add_original(original.clone());
+        c.add_original(Cookie::new("other".to_string(), "2".to_string()));
+        assert!(c.delta().is_empty());
+
+        c.touch("original");
+        c.touch("missing");
+        assert_eq!(c.delta(), vec![original.clone()]);
+        assert_eq!(c.find("original"), Some(original));
+        assert!(c.find("missing").is_none());
+    }
+
+    #[test]
+    fn deletion_for() {
+        let c = CookieJar::new(KEY);
+        assert!(c.deletion_for("foo").is_none());
+
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        cookie.path = Some("/app".to_string());
+        cookie.domain = Some("example.com".to_string());
+        cookie.secure = true;
+        cookie.httponly = true;
+        c.add(cookie);
+
+        let removal = c.deletion_for("foo").unwrap();
+        assert_eq!(removal.name, "foo");
+        assert_eq!(removal.value, "");
+        assert_eq!(removal.path, Some("/app".to_string()));
+        assert_eq!(removal.domain, Some("example.com".to_string()));
+        assert!(removal.secure);
+        assert!(!removal.httponly);
+        assert_eq!(removal.max_age, Some(0));
+        assert!(removal.expires.unwrap() < time::now_utc());
+        assert!(c.find("foo").is_some());
+    }
+
+    #[test]
+    fn from_request_header() {
+        let c = CookieJar::from_request_header(KEY, "a=1; b=2").unwrap();
+        assert_eq!(c.find("a"), Some(Cookie::new("a".to_string(), "1".to_string())));
+        assert_eq!(c.find("b"), Some(Cookie::new("b".to_string(), "2".to_string())));
+        assert!(c.delta().is_empty());
+
+        let c = CookieJar::from_request_header(KEY, " a=1 ;;b=2; ").unwrap();
+        assert_eq!(c.iter().count(), 2);
+        assert!(CookieJar::from_request_header(KEY, "").unwrap().iter().next().is_none());
+        assert!(CookieJar::from_request_header(KEY, "a=1; b").is_err());
+    }
+
+    #[test]
+    fn prefixes() {
+        let c = CookieJar::new(KEY);
+
+        c.add(Cookie::new("__Secure-id".to_string(), "1".to_string()));
+        let cookie = c.find("__Secure-id").unwrap();
+        assert!(cookie.secure);
+        assert!(cookie.path.is_none());
+
+        let mut cookie = Cookie::new("__Host-id".to_string(), "1".to_string());
+        cookie.path = Some("/".to_string());
+        c.add(cookie);
+        c.add(Cookie::new("__Host-id2".to_string(), "2".to_string()));
+        for name in &["__Host-id", "__Host-id2"] {
+            let cookie = c.find(name).unwrap();
+            assert!(cookie.secure);
+            assert_eq!(cookie.path, Some("/".to_string()));
+            assert!(cookie.domain.is_none());
+        }
+    }
+
+    #[test]
+    fn prefix_conflicts() {
+        let c = CookieJar::new(KEY);
+
+        let mut cookie = Cookie::new("__Host-id".to_string(), "1".to_string());
+        cookie.domain = Some("example.com".to_string());
+        assert!(c.try_add(cookie).is_err());
+
+        let mut cookie = Cookie::new("__Host-id".to_string(), "1".to_string());
+        cookie.path = Some("/foo".to_string());
+        assert!(c.try_add(cookie).is_err());
+        assert!(c.find("__Host-id").is_none());
+
+        let mut cookie = Cookie::new("__Secure-id".to_string(), "1".to_string());
+        cookie.domain = Some("example.com".to_string());
+        cookie.path = Some("/foo".to_string());
+        assert!(c.try_add(cookie).is_ok());
+    }
+
+    #[test]
+    fn prefix_conflict_not_added() {
+        let c = CookieJar::new(KEY);
+        c.add(Cookie::parse("__Host-id=1; Path=/").unwrap());
+
+        for header in &["__Host-id=2; Path=/admin", "__Host-id=2; Domain=evil.com",
+                        "__Host-id=2; Domain=evil.com; Path=/admin"] {
+            c.add(Cookie::parse(header).unwrap());
+            let stored = c.find("__Host-id").unwrap();
+            assert_eq!(stored.value, "1", "{}", header);
+            assert_eq!(stored.path, Some("/".to_string()));
+
+            c.batch(|b| b.add(Cookie::parse(&header.replace("id", "batch")).unwrap()));
+            assert!(c.find("__Host-batch").is_none(), "{}", header);
+        }
+        let names = c.delta().into_iter().map(|c| c.name).collect::<Vec<_>>();
+        assert_eq!(names, ["__Host-id"]);
+    }
+
+    #[test]
+    fn get_contains() {
+        let mut c = CookieJar::new(KEY);
+        c.add_original(Cookie::new("orig".to_string(), "1".to_string()));
+        c.add(Cookie::new("Name".to_string(), "2".to_string()));
+
+        assert_eq!(c.get("orig").unwrap().value, "1");
+        assert_eq!(c.get("Name").unwrap().value, "2");
+        assert!(c.contains("orig"));
+        assert!(c.contains("Name"));
+        assert!(c.get("name").is_none());
+        assert!(!c.contains("NAME"));
+        assert!(!c.contains("missing"));
+        assert!(c.get("").is_none());
+
+        c.remove("orig");
+        assert!(c.get("orig").is_none());
+        assert!(!c.contains("orig"));
+        assert_eq!(c.permanent().get("Name").unwrap().value, "2");
+    }
+
+    #[cfg(feature = "secure")]
+    #[test]
+    fn signed_get_contains() {
+        let c = CookieJar::new(KEY);
+        c.signed().add(Cookie::new("a".to_string(), "value".to_string()));
+        c.add(Cookie::new("plain".to_string(), "value".to_string()));
+
+        assert_eq!(c.signed().get("a").unwrap().value, "value");
+        assert!(c.get("a").unwrap().value != "value");
+        assert!(c.signed().contains("a"));
+        assert!(c.contains("plain"));
+        assert!(!c.signed().contains("plain"));
+        assert!(c.signed().get("plain").is_none());
+
+        let mut tampered = c.get("a").unwrap();
+        tampered.value.push('x');
+        c.add(tampered);
+        assert!(c.contains("a"));
+        assert!(!c.signed().contains("a"));
+    }
+
+    #[cfg(feature = "secure")]
+    #[test]
+    fn verify_named() {
+        let c = CookieJar::new(KEY);
+        c.signed().add(Cookie::new("a".to_string(), "value".to_string()));
+        let signed = c.find("a").unwrap().value;
+
+        assert_eq!(c.verify_named("a", &signed), Some("value".to_string()));
+        assert_eq!(c.verify_named("b", &signed), None);
+        assert_eq!(c.verify_named("a", "value"), None);
+        let mut tampered = signed.clone();
+        tampered.push('x');
+        assert_eq!(c.verify_named("a", &tampered), None);
+        assert_eq!(CookieJar::new(b"0123456789abcdef0123456789abcdef")
+                       .verify_named("a", &signed), None);
+    }
+
+    #[cfg(feature = "secure")]
+    #[test]
+    fn rotated_keys() {
+        const OLD: &'static [u8] = b"0123456789abcdef0123456789abcdef";
+
+        let old = CookieJar::new(OLD);
+        old.signed().add(Cookie::new("signed".to_string(), "1".to_string()));
+        old.encrypted().add(Cookie::new("encrypted".to_string(), "2".to_string()));
+        let signed = old.find("signed").unwrap();
+        let encrypted = old.find("encrypted").unwrap();
+
+        let mut c = CookieJar::with_keys(KEY, &[b"unrelated", OLD]);
+        c.add_original(signed.clone());
+        c.add_original(encrypted.clone());
+        assert_eq!(c.signed().find("signed").unwrap().value, "1");
+        assert_eq!(c.encrypted().find("encrypted").unwrap().value, "2");
+
+        let mut tampered = signed.clone();
+        tampered.value.push('x');
+        c.add_original(tampered);
+        assert!(c.signed().find("signed").is_none());
+
+        // New cookies are only written with the current key.
+        c.signed().add(Cookie::new("fresh".to_string(), "3".to_string()));
+        let mut current = CookieJar::new(KEY);
+        current.add_original(c.find("fresh").unwrap());
+        current.add_original(signed);
+        assert_eq!(current.signed().find("fresh").unwrap().value, "3");
+        assert!(current.signed().find("signed").is_none());
+    }
+
+    #[test]
+    fn delta_removals() {
+        let mut c = CookieJar::new(KEY);
+        c.add_original(Cookie::parse("b=1; Path=/app; Domain=example.com; Secure").unwrap());
+        c.add_original(Cookie::parse("a=1").unwrap());
+        c.add(Cookie::parse("z=1").unwrap());
+        c.add(Cookie::parse("y=1").unwrap());
+        c.remove("b");
+        c.remove("a");
+        c.remove("unknown");
+        c.remove("z");
+
+        let delta = c.delta();
+        let names = delta.iter().map(|c| &c.name[..]).collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b", "unknown", "z", "y"]);
+        assert!(delta[..4].iter().all(|c| c.value.is_empty() && c.max_age == Some(0)));
+        assert!(delta[0].path.is_none());
+        assert_eq!(delta[1].path, Some("/app".to_string()));
+        assert_eq!(delta[1].domain, Some("example.com".to_string()));
+        assert!(delta[1].secure);
+        assert_eq!(delta[4].value, "1");
+
+        c.add(Cookie::parse("b=2").unwrap());
+        assert_eq!(c.delta().len(), 5);
+        c.clear();
+        let delta = c.delta();
+        assert!(delta.iter().all(|c| c.max_age == Some(0)));
+        assert_eq!(delta.len(), 5);
+    }
+
+    #[test]
+    fn clear_domain() {
+        let mut c = CookieJar::new(KEY);
+        c.add_original(Cookie::parse("a=1; Domain=example.com; Path=/").unwrap());
+        c.add_original(Cookie::parse("b=1; Domain=.Example.com").unwrap());
+        c.add_original(Cookie::parse("c=1; Domain=www.example.com; Path=/app").unwrap());
+        c.add_original(Cookie::parse("d=1").unwrap());
+        c.add_original(Cookie::parse("e=1; Domain=other.com").unwrap());
+        c.add_original(Cookie::parse("f=1; Domain=com").unwrap());
+        c.add(Cookie::parse("g=1; Domain=notexample.com").unwrap());
+        c.clear_domain("EXAMPLE.com", Some("www.example.com"));
+
+        let delta = c.delta();
+        let names = delta.iter().map(|c| &c.name[..]).collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b", "c", "d", "g"]);
+        assert!(delta[..4].iter().all(|c| c.value.is_empty() && c.max_age == Some(0)));
+        assert_eq!(delta[0].domain, Some("example.com".to_string()));
+        assert_eq!(delta[0].path, Some("/".to_string()));
+        assert_eq!(delta[1].domain, Some("example.com".to_string()));
+        assert_eq!(delta[2].domain, Some("www.example.com".to_string()));
+        assert_eq!(delta[2].path, Some("/app".to_string()));
+        assert_eq!(delta[3].domain, None);
+        assert_eq!(delta[4].value, "1");
+
+        let names = c.iter().map(|c| c.name).collect::<Vec<_>>();
+        assert_eq!(names, ["e", "f", "g"]);
+
+        let mut c = CookieJar::new(KEY);
+        c.add_original(Cookie::parse("a=1; Domain=example.com").unwrap());
+        c.add_original(Cookie::parse("b=1; Domain=app.example.com").unwrap());
+        c.add_original(Cookie::parse("c=1; Domain=api.app.example.com").unwrap());
+        c.add_original(Cookie::parse("d=1; Domain=other.example.com").unwrap());
+        c.add_original(Cookie::parse("host=1").unwrap());
+        c.clear_domain("app.example.com", None);
+        let names = c.delta().into_iter().map(|c| c.name).collect::<Vec<_>>();
+        assert_eq!(names, ["b", "c"]);
+        let names = c.iter().map(|c| c.name).collect::<Vec<_>>();
+        assert_eq!(names, ["a", "d", "host"]);
+
+        let mut c = CookieJar::new(KEY);
+        c.add_original(Cookie::parse("host=1").unwrap());
+        c.add_original(Cookie::parse("a=1; Domain=example.com").unwrap());
+        c.clear_domain("other.com", None);
+        c.clear_domain("other.com", Some("example.com"));
+        c.clear_domain("www.example.com", Some("example.com"));
+        assert!(c.delta().is_empty());
+        assert!(c.contains("host"));
+        c.clear_domain("example.com", Some("EXAMPLE.com"));
+        let names = c.delta().into_iter().map(|c| c.name).collect::<Vec<_>>();
+        assert_eq!(names, ["a", "host"]);
+    }
+
+    #[test]
+    fn iter_sorted() {
+        let mut c = CookieJar::new(KEY);
+        c.add_original(Cookie::new("m".to_string(), "1".to_string()));
+        for name in &["z", "a", "B", "k"] {
+            c.add(Cookie::new(name.to_string(), "1".to_string()));
+        }
+        c.remove("k");
+
+        let names = c.iter().map(|c| c.name).collect::<Vec<_>>();
+        assert_eq!(names, ["B", "a", "m", "z"]);
+        let again = c.iter().map(|c| c.name).collect::<Vec<_>>();
+        assert_eq!(names, again);
+        assert!(CookieJar::new(KEY).iter().next().is_none());
+    }
+
+    #[test]
+    fn conditional_defaults() {
+        let c = CookieJar::new(KEY);
+        let mut template = Cookie::new(String::new(), String::new());
+        template.path = Some("/".to_string());
+        template.httponly = true;
+        template.custom.insert("Partitioned".to_string(), "1".to_string());
+        c.set_conditional_default(|c| c.name.starts_with("__Host-"), template);
+
+        let mut tagged = Cookie::new("tagged".to_string(), "1".to_string());
+        tagged.path = Some("/".to_string());
+        c.set_conditional_default(|c| c.path.is_none(), tagged);
+
+        c.add(Cookie::new("__Host-id".to_string(), "1".to_string()));
+        c.add(Cookie::new("theme".to_string(), "dark".to_string()));
+        let mut scoped = Cookie::new("scoped".to_string(), "1".to_string());
+        scoped.path = Some("/app".to_string());
+        c.add(scoped);
+
+        let host = c.find("__Host-id").unwrap();
+        assert_eq!(host.value, "1");
+        assert_eq!(host.path, Some("/".to_string()));
+        assert!(host.httponly);
+        assert!(host.secure);
+        assert_eq!(host.custom.get("Partitioned"), Some(&"1".to_string()));
+
+        let theme = c.find("theme").unwrap();
+        assert_eq!(theme.path, Some("/".to_string()));
+        assert!(!theme.httponly);
+        assert!(theme.custom.is_empty());
+
+        let scoped = c.find("scoped").unwrap();
+        assert_eq!(scoped.path, Some("/app".to_string()));
+        assert!(!scoped.httponly);
+
+        let mut all = Cookie::new(String::new(), String::new());
+        all.secure = true;
+        c.set_default(all);
+        c.add(Cookie::new("late".to_string(), "1".to_string()));
+        assert!(c.find("late").unwrap().secure);
+        assert!(!c.find("theme").unwrap().secure);
+    }
+
     #[cfg(features = "secure")]
     #[test]
     fn chained() {
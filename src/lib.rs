@@ -23,19 +23,47 @@ extern crate url;
 extern crate time;
 #[cfg(feature = "serialize-rustc")] extern crate rustc_serialize;
 #[cfg(feature = "serialize-serde")] extern crate serde;
+#[cfg(feature = "serialize-serde")] extern crate serde_json;
+#[cfg(feature = "chrono")] extern crate chrono;
 
 use std::ascii::AsciiExt;
-use std::collections::BTreeMap;
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::HashMap;
+use std::error;
 use std::fmt;
+use std::net::IpAddr;
 use std::str::FromStr;
+use std::usize;
 
 #[cfg(feature = "serialize-serde")] use serde::{Serialize, Deserialize};
+use url::Url;
+use url::percent_encoding::{percent_encode, percent_decode, EncodeSet};
 
-pub use jar::CookieJar;
+pub use borrowed::CookieRef;
+pub use builder::CookieBuilder;
+pub use custom::CustomAttributes;
+pub use jar::{BatchContext, CookieJar, MergeStrategy};
+pub use sync_jar::SyncCookieJar;
+mod borrowed;
+mod builder;
+mod custom;
 mod jar;
+mod sync_jar;
 
 /// Holds all the data for a single cookie
-#[derive(PartialEq, Clone, Debug)]
+///
+/// The `Default` cookie has an empty name and value and no attributes, the
+/// same as `Cookie::new(String::new(), String::new())`, which suits struct
+/// update syntax:
+///
+/// ```
+/// use cookie::Cookie;
+///
+/// let c = Cookie { name: "foo".into(), secure: true, ..Default::default() };
+/// assert_eq!(c.to_string(), "foo=; Secure");
+/// ```
+#[derive(PartialEq, Clone, Debug, Default)]
 #[cfg_attr(feature = "serialize-rustc", derive(RustcEncodable, RustcDecodable))]
 pub struct Cookie {
     #[allow(missing_docs)]
@@ -55,12 +83,236 @@ pub struct Cookie {
     #[allow(missing_docs)]
     pub httponly: bool,
     #[allow(missing_docs)]
-    pub custom: BTreeMap<String, String>,
+    pub same_site: Option<SameSite>,
+    #[allow(missing_docs)]
+    pub priority: Option<Priority>,
+    #[allow(missing_docs)]
+    pub custom: CustomAttributes,
+    /// Unrecognized attributes without a value, such as `Partitioned`
+    pub flag_attrs: Vec<String>,
 }
 
 /// Crate-level error type used to indicate a problem with parsing
-#[derive(Debug)]
-pub struct Error;
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Error {
+    /// The header doesn't start with a `name=value` pair
+    MissingPair,
+    /// The cookie's name is empty
+    EmptyName,
+    /// The cookie's name or value contains a control character
+    ControlCharacter,
+    /// A percent-encoded name or value doesn't decode to UTF-8
+    InvalidEncoding,
+    /// A date couldn't be parsed or doesn't describe a real date
+    InvalidDate(String),
+    /// A `Max-Age` couldn't be parsed or is out of range
+    InvalidMaxAge(String),
+    /// A path doesn't start with `/`
+    InvalidPath(String),
+    /// The cookie's attributes conflict with its `__Secure-` or `__Host-`
+    /// name prefix
+    PrefixMismatch,
+    /// The formatted cookie is longer than allowed
+    TooLarge,
+    /// A JSON cookie is missing a member or has one of the wrong type
+    InvalidJson(String),
+    /// A custom attribute has the name of an attribute with its own field,
+    /// such as `Path`
+    ReservedAttribute(String),
+    /// The cookie's name isn't an RFC 6265 token
+    InvalidName(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidDate(ref s) => write!(f, "invalid cookie date: {}", s),
+            Error::InvalidMaxAge(ref s) => write!(f, "invalid cookie max-age: {}", s),
+            Error::InvalidPath(ref s) => write!(f, "cookie path must start with '/': {}", s),
+            Error::InvalidJson(ref s) => write!(f, "invalid JSON cookie member: {}", s),
+            Error::ReservedAttribute(ref s) => {
+                write!(f, "custom cookie attribute has a reserved name: {}", s)
+            }
+            Error::InvalidName(ref s) => write!(f, "invalid cookie name: {}", s),
+            _ => f.write_str(error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::MissingPair => "cookie has no name=value pair",
+            Error::EmptyName => "cookie name is empty",
+            Error::ControlCharacter => "cookie contains a control character",
+            Error::InvalidEncoding => "cookie is not percent-encoded UTF-8",
+            Error::InvalidDate(..) => "invalid cookie date",
+            Error::InvalidMaxAge(..) => "invalid cookie max-age",
+            Error::InvalidPath(..) => "cookie path must start with '/'",
+            Error::PrefixMismatch => "cookie attributes conflict with its name prefix",
+            Error::TooLarge => "cookie is too large",
+            Error::InvalidJson(..) => "invalid JSON cookie",
+            Error::ReservedAttribute(..) => "custom cookie attribute has a reserved name",
+            Error::InvalidName(..) => "invalid cookie name",
+        }
+    }
+}
+
+/// The error returned by `Cookie::parse_detailed`, locating the problem in
+/// the parsed string
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct ParseError {
+    /// What is wrong with the cookie
+    pub kind: ParseErrorKind,
+    /// The byte offset in the parsed string at which the problem was found
+    pub offset: usize,
+}
+
+/// The kind of problem found by `Cookie::parse_detailed`
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ParseErrorKind {
+    /// The first `;`-separated segment has no `=`, and `offset` is its end
+    MissingEquals,
+    /// The cookie's name is empty, and `offset` is where it should start
+    EmptyName,
+    /// The name contains a control character, found at `offset`
+    ControlCharInName,
+    /// The value contains a control character, found at `offset`
+    ControlCharInValue,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", error::Error::description(self), self.offset)
+    }
+}
+
+impl error::Error for ParseError {
+    fn description(&self) -> &str {
+        match self.kind {
+            ParseErrorKind::MissingEquals => "cookie pair has no '='",
+            ParseErrorKind::EmptyName => "cookie name is empty",
+            ParseErrorKind::ControlCharInName => "cookie name contains a control character",
+            ParseErrorKind::ControlCharInValue => "cookie value contains a control character",
+        }
+    }
+}
+
+/// A problem with an attribute which `Cookie::parse_verbose` ignored
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ParseWarning {
+    /// An `Expires` date couldn't be parsed
+    InvalidExpires(String),
+    /// An `Expires` date couldn't be parsed and appears to use day or month
+    /// names in a language other than English
+    LocalizedExpires(String),
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseWarning::InvalidExpires(ref s) => {
+                write!(f, "ignored invalid Expires date: {}", s)
+            }
+            ParseWarning::LocalizedExpires(ref s) => {
+                write!(f, "ignored Expires date with non-English names: {}", s)
+            }
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        match err.kind {
+            ParseErrorKind::MissingEquals => Error::MissingPair,
+            ParseErrorKind::EmptyName => Error::EmptyName,
+            ParseErrorKind::ControlCharInName |
+            ParseErrorKind::ControlCharInValue => Error::ControlCharacter,
+        }
+    }
+}
+
+/// The value of a cookie's `SameSite` attribute
+///
+/// Parsed case-insensitively and always displayed with its canonical casing.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serialize-rustc", derive(RustcEncodable, RustcDecodable))]
+pub enum SameSite {
+    /// `SameSite=Strict`, never sent with cross-site requests
+    Strict,
+    /// `SameSite=Lax`, only sent with cross-site top-level navigations
+    Lax,
+    /// `SameSite=None`, sent with all cross-site requests
+    None,
+}
+
+impl SameSite {
+    /// Returns every `SameSite` value, from most to least restrictive
+    pub fn all() -> &'static [SameSite] {
+        static ALL: [SameSite; 3] = [SameSite::Strict, SameSite::Lax, SameSite::None];
+        &ALL
+    }
+
+    fn parse(s: &str) -> Option<SameSite> {
+        match &s.to_ascii_lowercase()[..] {
+            "strict" => Some(SameSite::Strict),
+            "lax" => Some(SameSite::Lax),
+            "none" => Some(SameSite::None),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        })
+    }
+}
+
+/// The value of a cookie's `Priority` attribute
+///
+/// Parsed case-insensitively and always displayed with its canonical casing.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serialize-rustc", derive(RustcEncodable, RustcDecodable))]
+pub enum Priority {
+    /// `Priority=Low`, evicted first when a browser's cookie limit is reached
+    Low,
+    /// `Priority=Medium`, the default priority
+    Medium,
+    /// `Priority=High`, evicted last when a browser's cookie limit is reached
+    High,
+}
+
+impl Priority {
+    /// Returns every `Priority` value, from lowest to highest
+    pub fn all() -> &'static [Priority] {
+        static ALL: [Priority; 3] = [Priority::Low, Priority::Medium, Priority::High];
+        &ALL
+    }
+
+    fn parse(s: &str) -> Option<Priority> {
+        match &s.to_ascii_lowercase()[..] {
+            "low" => Some(Priority::Low),
+            "medium" => Some(Priority::Medium),
+            "high" => Some(Priority::High),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Priority::Low => "Low",
+            Priority::Medium => "Medium",
+            Priority::High => "High",
+        })
+    }
+}
 
 impl Cookie {
     /// Creates a new `Cookie` instance from key and value strings
@@ -84,12 +336,99 @@ impl Cookie {
             path: None,
             secure: false,
             httponly: false,
-            custom: BTreeMap::new(),
+            same_site: None,
+            priority: None,
+            custom: CustomAttributes::new(),
+            flag_attrs: Vec::new(),
         }
     }
 
+    /// Creates a `CookieBuilder` for a cookie with the given name and value
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # extern crate cookie;
+    /// # extern crate time;
+    /// # fn main() {
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::build("foo", "bar")
+    ///     .expires_in(time::Duration::hours(1))
+    ///     .finish();
+    /// assert_eq!(c.name, "foo");
+    /// assert!(c.expires.is_some());
+    /// # }
+    /// ```
+    pub fn build<N, V>(name: N, value: V) -> CookieBuilder
+        where N: Into<String>, V: Into<String>
+    {
+        CookieBuilder::new(name, value)
+    }
+
+    /// Creates a new `CookieBuilder` for a cookie which removes the cookie
+    /// `name` from a browser
+    ///
+    /// The cookie has an empty value, `Max-Age=0` and an `Expires` at the
+    /// epoch. See `CookieBuilder::removal`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::build_removal("foo").path("/").finish();
+    /// assert_eq!(c.max_age, Some(0));
+    /// assert_eq!(c.path, Some("/".to_string()));
+    /// ```
+    pub fn build_removal<N>(name: N) -> CookieBuilder
+        where N: Into<String>
+    {
+        CookieBuilder::removal(name)
+    }
+
+    /// Creates a cookie which removes the cookie `name` from a browser when
+    /// sent as a `Set-Cookie` header
+    ///
+    /// The cookie has an empty value, `Max-Age=0` and an `Expires` at the
+    /// epoch. A browser only removes the stored cookie whose `Path` and
+    /// `Domain` match, so pass the ones it was set with, or `None` for a
+    /// cookie set without them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::make_removal("session", Some("/"), None);
+    /// assert_eq!(c.to_string(),
+    ///            "session=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT");
+    /// ```
+    pub fn make_removal<N>(name: N, path: Option<&str>, domain: Option<&str>) -> Cookie
+        where N: Into<String>
+    {
+        let mut c = CookieBuilder::removal(name).finish();
+        c.path = path.map(|p| p.to_string());
+        c.domain = domain.map(|d| d.to_string());
+        c
+    }
+
     /// Attempts to parse a string into a `Cookie` instance
     ///
+    /// The name/value pair is everything up to the first `;`, split at its
+    /// first `=`, so a value may itself contain `=`. Every later `;`-separated
+    /// segment is an attribute, which means a value can never contain `;`;
+    /// use `encoded` and `parse_encoded` to percent-encode such values.
+    ///
+    /// The pair must come first. A header which starts with an attribute,
+    /// such as `Secure; foo=bar`, is an error since `Secure` has no `=`, while
+    /// `Path=/; foo=bar` parses as a cookie named `Path`.
+    ///
+    /// Whitespace surrounding the name and value is removed. A value wrapped
+    /// in double quotes has the quotes removed, keeping any whitespace inside
+    /// them, so `foo=" bar "` has the value ` bar `. A lone or unbalanced
+    /// quote is kept as part of the value.
+    ///
     /// # Example
     ///
     /// ```
@@ -101,258 +440,3521 @@ impl Cookie {
     /// assert!(c.httponly);
     /// ```
     pub fn parse(s: &str) -> Result<Cookie, Error> {
-        macro_rules! unwrap_or_skip{ ($e:expr) => (
-            match $e { Some(s) => s, None => continue, }
-        ) }
-
-        let mut c = Cookie::new(String::new(), String::new());
-        let mut pairs = s.trim().split(';');
-        let keyval = match pairs.next() {
-            Some(s) => s,
-            _ => {
-                return Err(Error);
-            }
-        };
-        let (name, value) = try!(split(keyval));
-        c.name = name.into();
-        if c.name.is_empty() {
-            return Err(Error);
-        }
-        c.value = value.into();
-
-        for attr in pairs {
-            let (k, v) = attr_split(attr);
-            match (&k.to_ascii_lowercase()[..], v) {
-                ("secure", _) => c.secure = true,
-                ("httponly", _) => c.httponly = true,
-                ("max-age", Some(v)) => {
-                    // See RFC 6265 Section 5.2.2, negative values
-                    // indicate that the earliest possible expiration
-                    // time should be used, so set the max age as 0
-                    // seconds.
-                    let max_age: i64 = unwrap_or_skip!(v.parse().ok());
-                    c.max_age = Some(if max_age < 0 {
-                        0
-                    } else {
-                        max_age as u64
-                    });
-                },
-                ("domain", Some(v)) => {
-                    if v.is_empty() {
-                        continue;
-                    }
+        Cookie::parse_with(trim_header(s), usize::MAX)
+    }
 
-                    let domain = if v.chars().next() == Some('.') {
-                        &v[1..]
-                    } else {
-                        v
-                    };
-                    c.domain = Some(domain.to_ascii_lowercase());
-                }
-                ("path", Some(v)) => c.path = Some(v.to_string()),
-                ("expires", Some(v)) => {
-                    // Try strptime with three date formats according to
-                    // http://tools.ietf.org/html/rfc2616#section-3.3.1
-                    // Try additional ones as encountered in the real world.
-                    let tm = time::strptime(v, "%a, %d %b %Y %H:%M:%S %Z").or_else(|_| {
-                        time::strptime(v, "%A, %d-%b-%y %H:%M:%S %Z")
-                    }).or_else(|_| {
-                        time::strptime(v, "%a, %d-%b-%Y %H:%M:%S %Z")
-                    }).or_else(|_| {
-                        time::strptime(v, "%a %b %d %H:%M:%S %Y")
-                    });
-                    let tm = unwrap_or_skip!(tm.ok());
-                    c.expires = Some(tm);
-                }
-                (_, Some(v)) => {c.custom.insert(k.to_string(), v.to_string());}
-                (_, _) => {}
-            }
+    /// Attempts to parse a string into a `Cookie` instance, rejecting names
+    /// which RFC 6265 doesn't allow
+    ///
+    /// `parse` accepts any non-empty name without a control character, while
+    /// this also returns `Error::InvalidName` for a name which isn't a token,
+    /// as checked by `is_valid_name`: whitespace, non-ASCII characters and
+    /// separators such as `@` or `/` aren't allowed. Everything else is parsed
+    /// exactly like `parse`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::{Cookie, Error};
+    ///
+    /// assert!(Cookie::parse("fo o=bar").is_ok());
+    /// assert_eq!(Cookie::parse_strict("fo o=bar"),
+    ///            Err(Error::InvalidName("fo o".to_string())));
+    /// ```
+    pub fn parse_strict(s: &str) -> Result<Cookie, Error> {
+        let c = try!(Cookie::parse(s));
+        if !is_valid_name(&c.name) {
+            return Err(Error::InvalidName(c.name))
         }
+        Ok(c)
+    }
 
-        return Ok(c);
+    /// Attempts to parse an already trimmed string into a `Cookie` instance
+    ///
+    /// `parse` first strips any leading byte order mark and surrounding
+    /// whitespace from the whole string, while this assumes the caller has
+    /// already done so. Whitespace around the name, the value and each
+    /// attribute is still removed as described in RFC 6265.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse_no_trim("foo = bar;\tSecure").unwrap();
+    /// assert_eq!(c.name, "foo");
+    /// assert_eq!(c.value, "bar");
+    /// assert!(c.secure);
+    /// ```
+    pub fn parse_no_trim(s: &str) -> Result<Cookie, Error> {
+        Cookie::parse_with(s, usize::MAX)
+    }
 
-        fn attr_split<'a>(s: &'a str) -> (&'a str, Option<&'a str>) {
-            match s.find("=") {
-                Some(pos) => {
-                    let parts = s.split_at(pos);
-                    let value = parts.1[1..].trim();
-                    (parts.0.trim(), Some(value))
-                }
-                None => (s.trim(), None)
-            }
-        }
+    /// Attempts to parse a string into a `Cookie` instance, reporting where
+    /// parsing failed
+    ///
+    /// This parses exactly like `parse`, but on failure returns a
+    /// `ParseError` with the kind of problem and its byte offset in `s`, so
+    /// that callers can point at the offending part of a header.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::{Cookie, ParseErrorKind};
+    ///
+    /// let err = Cookie::parse_detailed("foo=b\nar").unwrap_err();
+    /// assert_eq!(err.kind, ParseErrorKind::ControlCharInValue);
+    /// assert_eq!(err.offset, 5);
+    /// assert_eq!(err.to_string(), "cookie value contains a control character at byte 5");
+    /// ```
+    pub fn parse_detailed(s: &str) -> Result<Cookie, ParseError> {
+        let err = match Cookie::parse(s) {
+            Ok(c) => return Ok(c),
+            Err(err) => err,
+        };
+
+        // Locate the problem in the same pair `parse` looked at, counting
+        // offsets from the start of `s` rather than the trimmed string.
+        let trimmed = trim_header(s);
+        let start = trimmed.as_ptr() as usize - s.as_ptr() as usize;
+        let pair = trimmed.split(';').next().unwrap_or("");
+        let eq = pair.find('=').unwrap_or(pair.len());
+        let name_start = eq - pair[..eq].trim_left().len();
+        let name = pair[..eq].trim();
+        let value = pair.get(eq + 1..).unwrap_or("");
+        let value_start = eq + 1 + value.len() - value.trim_left().len();
+        let control = |s: &str| {
+            s.char_indices().find(|&(_, ch)| ch.is_control()).map(|(i, _)| i)
+        };
+
+        let (kind, offset) = match err {
+            Error::EmptyName => (ParseErrorKind::EmptyName, name_start),
+            Error::ControlCharacter => match control(name) {
+                Some(i) => (ParseErrorKind::ControlCharInName, name_start + i),
+                None => (ParseErrorKind::ControlCharInValue,
+                         value_start + control(value.trim()).unwrap_or(0)),
+            },
+            _ => (ParseErrorKind::MissingEquals, pair.len()),
+        };
+        Err(ParseError { kind: kind, offset: start + offset })
+    }
 
-        fn split<'a>(s: &'a str) -> Result<(&'a str, &'a str), Error> {
-            macro_rules! try {
-                ($e:expr) => (match $e {
-                    Some(s) => s,
-                    None => return Err(Error)
-                })
+    /// Attempts to parse a string into a `Cookie` instance, also returning a
+    /// warning for each attribute which was ignored because it is malformed
+    ///
+    /// The cookie is exactly the one `parse` returns. Currently an `Expires`
+    /// date which can't be parsed produces a warning, which notes when the
+    /// date seems to use localized day or month names, as some misconfigured
+    /// servers send.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::{Cookie, ParseWarning};
+    ///
+    /// let (c, warnings) =
+    ///     Cookie::parse_verbose("foo=bar; Expires=dim., 23 nov. 2014 20:00:00 GMT").unwrap();
+    /// assert_eq!(c.value, "bar");
+    /// assert!(c.expires.is_none());
+    /// assert_eq!(warnings, [ParseWarning::LocalizedExpires("dim., 23 nov. 2014 20:00:00 GMT"
+    ///                                                          .to_string())]);
+    /// ```
+    pub fn parse_verbose(s: &str) -> Result<(Cookie, Vec<ParseWarning>), Error> {
+        let c = try!(Cookie::parse(s));
+        let mut warnings = Vec::new();
+        for attr in trim_header(s).split(';').skip(1) {
+            let mut parts = attr.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => continue,
+            };
+            if !key.eq_ignore_ascii_case("expires") || value.is_empty() ||
+               parse_expires(value).is_some() {
+                continue
             }
-            let mut parts = s.trim().splitn(2, '=');
-            let first = try!(parts.next()).trim();
-            let second = try!(parts.next()).trim();
-            Ok((first, second))
+            warnings.push(if is_localized_date(value) {
+                ParseWarning::LocalizedExpires(value.to_string())
+            } else {
+                ParseWarning::InvalidExpires(value.to_string())
+            });
         }
+        Ok((c, warnings))
     }
 
-    /// Returns the (name, value) pair for this `Cookie` instance
-    pub fn pair(&self) -> AttrVal {
-        AttrVal(&self.name, &self.value)
+    /// Attempts to parse a string into a `Cookie` instance, also returning
+    /// whether parsing normalized any of its attributes
+    ///
+    /// The cookie is exactly the one `parse` returns. The flag is set if the
+    /// `Domain` or `Max-Age` written to the cookie differs from the one in
+    /// `s`, such as a domain which was lowercased or stripped of a leading
+    /// dot or port, or a max age which was negative or too large and so was
+    /// clamped. It is also set if one of those attributes was dropped, such
+    /// as a malformed `Max-Age` or all but the last of repeated ones. A proxy
+    /// can use it to forward the original header unless it needed fixing.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let (c, changed) = Cookie::parse_normalized("foo=bar; Domain=.EXAMPLE.com").unwrap();
+    /// assert_eq!(c.domain, Some("example.com".to_string()));
+    /// assert!(changed);
+    /// assert!(!Cookie::parse_normalized("foo=bar").unwrap().1);
+    /// ```
+    pub fn parse_normalized(s: &str) -> Result<(Cookie, bool), Error> {
+        let c = try!(Cookie::parse(s));
+        let max_age = c.max_age.map(|m| m.to_string());
+        let changed = trim_header(s).split(';').skip(1).any(|attr| {
+            let mut parts = attr.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => return false,
+            };
+            if value.is_empty() {
+                false
+            } else if key.eq_ignore_ascii_case("domain") {
+                c.domain.as_ref().map(|d| &d[..]) != Some(value)
+            } else if key.eq_ignore_ascii_case("max-age") {
+                max_age.as_ref().map(|m| &m[..]) != Some(value)
+            } else {
+                false
+            }
+        });
+        Ok((c, changed))
     }
-}
 
-#[cfg(feature = "serialize-serde")]
-impl Serialize for Cookie {
-    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
-        where S: serde::Serializer
-    {
-        serializer.serialize_str(&*self.to_string())
+    /// Parses the value of a `Cookie` request header into one `Cookie` per
+    /// `name=value` pair
+    ///
+    /// Unlike a `Set-Cookie` header, a request header has no attributes, so
+    /// every `;`-separated segment is a cookie of its own. Whitespace around
+    /// each pair is removed and empty segments, such as one after a trailing
+    /// `;`, are skipped. An error is returned if any other segment isn't a
+    /// valid pair.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let cookies = Cookie::parse_request_header("a=1; b=2;c=3;").unwrap();
+    /// assert_eq!(cookies.len(), 3);
+    /// assert_eq!(cookies[2].name, "c");
+    /// ```
+    pub fn parse_request_header(s: &str) -> Result<Vec<Cookie>, Error> {
+        let mut cookies = Vec::new();
+        for pair in trim_header(s).split(';') {
+            if pair.trim().is_empty() {
+                continue
+            }
+            cookies.push(try!(Cookie::parse_no_trim(pair)));
+        }
+        Ok(cookies)
     }
-}
 
-#[cfg(feature = "serialize-serde")]
-impl Deserialize for Cookie {
-    fn deserialize<D>(deserializer: &mut D) -> Result<Cookie, D::Error>
-        where D: serde::Deserializer
-    {
-        deserializer.deserialize_string(CookieVisitor)
+    /// Attempts to parse a string into a `CookieRef` which borrows from it
+    ///
+    /// This parses exactly like `parse`, but without allocating a `String`
+    /// for the name, value or any attribute, which suits reading cookies out
+    /// of a header only to discard them. The one difference is that the
+    /// `Domain` isn't lowercased until `CookieRef::into_owned` is called.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse_borrowed("foo=bar; HttpOnly").unwrap();
+    /// assert_eq!(c.name, "foo");
+    /// assert!(c.httponly);
+    /// ```
+    pub fn parse_borrowed(s: &str) -> Result<CookieRef, Error> {
+        borrowed::parse(trim_header(s), usize::MAX)
     }
-}
-#[cfg(feature = "serialize-serde")]
-struct CookieVisitor;
 
-#[cfg(feature = "serialize-serde")]
-impl serde::de::Visitor for CookieVisitor {
-    type Value = Cookie;
+    /// Attempts to parse raw header bytes into a `Cookie` instance
+    ///
+    /// The bytes are decoded with `String::from_utf8_lossy`, so invalid UTF-8
+    /// such as a Latin-1 value never causes an error. This is lossy: each
+    /// invalid sequence is replaced with U+FFFD, the replacement character,
+    /// and the original bytes can't be recovered from the result.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse_bytes_lossy(b"foo=caf\xe9; Path=/").unwrap();
+    /// assert_eq!(c.value, "caf\u{fffd}");
+    /// ```
+    pub fn parse_bytes_lossy(b: &[u8]) -> Result<Cookie, Error> {
+        Cookie::parse(&String::from_utf8_lossy(b))
+    }
 
-    fn visit_str<E>(&mut self, v: &str) -> Result<Cookie, E>
-        where E: serde::de::Error
-    {
-        match Cookie::parse(v) {
-            Ok(cookie) => Ok(cookie),
-            Err(_) => Err(serde::de::Error::custom("Could not parse serialized cookie!"))
+    /// Attempts to parse a string into a `Cookie` instance, also accepting a
+    /// value wrapped in single quotes
+    ///
+    /// This is a lenient extension to RFC 6265, which only allows a value to
+    /// be wrapped in double quotes. Some frameworks nonetheless emit values
+    /// such as `foo='bar'`, so a value which both starts and ends with `'` has
+    /// those quotes removed. Otherwise this parses exactly like `parse`,
+    /// which keeps single quotes as part of the value.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse_lenient("foo='bar'; Path=/").unwrap();
+    /// assert_eq!(c.value, "bar");
+    /// assert_eq!(Cookie::parse("foo='bar'").unwrap().value, "'bar'");
+    /// ```
+    pub fn parse_lenient(s: &str) -> Result<Cookie, Error> {
+        let mut c = try!(Cookie::parse(s));
+        if c.value.len() >= 2 && c.value.starts_with('\'') && c.value.ends_with('\'') {
+            c.value = c.value[1..c.value.len() - 1].to_string();
         }
+        Ok(c)
     }
-}
 
-/// Represents a key/value pair
-pub struct AttrVal<'a>(pub &'a str, pub &'a str);
+    /// Attempts to parse a string into a `Cookie` instance, keeping at most
+    /// `max_attrs` attributes
+    ///
+    /// Unrecognized attributes are collected into `custom` just like `parse`,
+    /// but every attribute after the first `max_attrs` is ignored. This bounds
+    /// the amount of data stored for headers with an untrusted number of
+    /// attributes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse_lenient_limited("foo=bar; Secure; a=1; b=2", 2).unwrap();
+    /// assert!(c.secure);
+    /// assert_eq!(c.custom.len(), 1);
+    /// ```
+    pub fn parse_lenient_limited(s: &str, max_attrs: usize) -> Result<Cookie, Error> {
+        Cookie::parse_with(trim_header(s), max_attrs)
+    }
 
-impl<'a> fmt::Display for AttrVal<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let AttrVal(ref attr, ref val) = *self;
-        write!(f, "{}={}", attr, val)
+    /// Parses every `Set-Cookie` header in a block of HTTP response headers
+    ///
+    /// Each line of `block` starting with `Set-Cookie:`, matched
+    /// case-insensitively, is parsed with `parse`. All other lines are
+    /// ignored.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let block = "HTTP/1.1 200 OK\r\n\
+    ///              Set-Cookie: a=1; Path=/\r\n\
+    ///              Content-Type: text/html\r\n\
+    ///              set-cookie: b=2\r\n";
+    /// let cookies = Cookie::parse_header_block(block);
+    /// assert_eq!(cookies.len(), 2);
+    /// assert_eq!(cookies[1].as_ref().unwrap().name, "b");
+    /// ```
+    pub fn parse_header_block(block: &str) -> Vec<Result<Cookie, Error>> {
+        const HEADER: &'static str = "set-cookie:";
+
+        block.lines().filter_map(|line| {
+            if line.len() >= HEADER.len() &&
+               line.is_char_boundary(HEADER.len()) &&
+               line[..HEADER.len()].eq_ignore_ascii_case(HEADER) {
+                Some(Cookie::parse(&line[HEADER.len()..]))
+            } else {
+                None
+            }
+        }).collect()
     }
-}
+
+    /// Extracts as many cookies as possible from a malformed header, never
+    /// failing
+    ///
+    /// This is meant for tooling over messy data such as logs, where several
+    /// `Set-Cookie` values may have been joined together. The input is split
+    /// into lines, a leading `Set-Cookie:` is dropped from each, and lines
+    /// are further split at every comma which is followed by something that
+    /// looks like a `name=value` pair, which keeps the comma in `Expires`
+    /// dates intact. Control characters are removed from each piece before
+    /// it is parsed with `parse`, and pieces which still can't be parsed are
+    /// skipped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let header = "a=1; Expires=Wed, 09 Jun 2021 10:18:14 GMT, b=2; Secure";
+    /// let cookies = Cookie::parse_best_effort(header);
+    /// assert_eq!(cookies.len(), 2);
+    /// assert!(cookies[0].expires.is_some());
+    /// assert!(cookies[1].secure);
+    /// ```
+    pub fn parse_best_effort(s: &str) -> Vec<Cookie> {
+        const HEADER: &'static str = "set-cookie:";
+
+        fn starts_pair(s: &str) -> bool {
+            let pair = s.split(';').next().unwrap_or("");
+            match pair.find('=') {
+                Some(pos) => is_valid_name(pair[..pos].trim()),
+                None => false,
+            }
+        }
+
+        let mut cookies = Vec::new();
+        for line in s.lines() {
+            let mut line = line.trim();
+            if line.len() >= HEADER.len() &&
+               line.is_char_boundary(HEADER.len()) &&
+               line[..HEADER.len()].eq_ignore_ascii_case(HEADER) {
+                line = &line[HEADER.len()..];
+            }
+
+            let mut pieces = Vec::new();
+            let mut start = 0;
+            for (pos, _) in line.match_indices(',') {
+                if starts_pair(&line[pos + 1..]) {
+                    pieces.push(&line[start..pos]);
+                    start = pos + 1;
+                }
+            }
+            pieces.push(&line[start..]);
+
+            for piece in pieces {
+                let piece = piece.chars()
+                                 .filter(|ch| !ch.is_control())
+                                 .collect::<String>();
+                if let Ok(cookie) = Cookie::parse(&piece) {
+                    cookies.push(cookie);
+                }
+            }
+        }
+        cookies
+    }
+
+    /// Attempts to parse a string into a `Cookie` instance, percent-decoding
+    /// its name and value
+    ///
+    /// Both upper and lower case hex digits are accepted in escapes. This is
+    /// the inverse of `encoded`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse_encoded("foo=bar%20baz%3B; Path=/").unwrap();
+    /// assert_eq!(c.value, "bar baz;");
+    /// ```
+    pub fn parse_encoded(s: &str) -> Result<Cookie, Error> {
+        let mut c = try!(Cookie::parse(s));
+        c.name = try!(decode(&c.name));
+        c.value = try!(decode(&c.value));
+        return Ok(c);
+
+        fn decode(s: &str) -> Result<String, Error> {
+            match percent_decode(s.as_bytes()).decode_utf8() {
+                Ok(s) => Ok(s.into_owned()),
+                Err(_) => Err(Error::InvalidEncoding),
+            }
+        }
+    }
+
+    fn parse_with(s: &str, max_attrs: usize) -> Result<Cookie, Error> {
+        borrowed::parse(s, max_attrs).map(CookieRef::into_owned)
+    }
+
+    /// Returns the (name, value) pair for this `Cookie` instance
+    ///
+    /// The value is exactly `value`, without the double quotes `to_string`
+    /// may add around it.
+    pub fn pair(&self) -> AttrVal {
+        AttrVal(&self.name, &self.value)
+    }
+
+    // The value as written in a header. A value with a space or comma is
+    // wrapped in double quotes, which `parse` removes again, as is a value
+    // which is itself wrapped in quotes so that those survive parsing.
+    fn quoted_value(&self) -> Cow<str> {
+        let v = &self.value;
+        let wrapped = v.len() >= 2 && v.starts_with('"') && v.ends_with('"');
+        if wrapped || v.contains(|c| c == ' ' || c == ',') {
+            Cow::Owned(format!("\"{}\"", v))
+        } else {
+            Cow::Borrowed(v)
+        }
+    }
+
+    /// Returns a wrapper which displays this cookie with its name and value
+    /// percent-encoded
+    ///
+    /// Escapes always use upper case hex digits, as recommended by RFC 3986.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::new("foo".into(), "a/b c".into());
+    /// assert_eq!(c.encoded().to_string(), "foo=a/b%20c");
+    /// ```
+    pub fn encoded(&self) -> EncodedCookie {
+        EncodedCookie { cookie: self }
+    }
+
+    /// Percent-decodes this cookie's value into raw bytes
+    ///
+    /// This undoes the encoding done by `encoded` for a value which may not be
+    /// valid UTF-8, which `parse_encoded` would reject.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse("foo=bar%20baz%3B%FF").unwrap();
+    /// assert_eq!(c.decode_value(), b"bar baz;\xff");
+    /// ```
+    pub fn decode_value(&self) -> Vec<u8> {
+        percent_decode(self.value.as_bytes()).collect()
+    }
+
+    /// Percent-encodes this cookie's value if it isn't a valid cookie value,
+    /// returning whether it was changed
+    ///
+    /// A value which `is_valid_value` accepts is left untouched. Any other
+    /// value is encoded as by `encoded`, and can be decoded again with
+    /// `parse_encoded`. A `true` result is a hint that the value may not be
+    /// what should be stored in a cookie in the first place.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let mut c = Cookie::new("foo".into(), "a b".into());
+    /// assert!(c.escape_value());
+    /// assert_eq!(c.value, "a%20b");
+    /// assert!(!c.escape_value());
+    /// ```
+    pub fn escape_value(&mut self) -> bool {
+        if is_valid_value(&self.value) {
+            return false
+        }
+        self.value = percent_encode(self.value.as_bytes(), COOKIE_ENCODE_SET).to_string();
+        true
+    }
+
+    /// Returns whether this cookie has expired at the time `now`
+    ///
+    /// A `max_age` takes precedence over `expires`, as in RFC 6265. Since a
+    /// `Cookie` doesn't record when it was received, only a `max_age` of zero
+    /// is treated as expired. A cookie with neither attribute is a session
+    /// cookie and never expires.
+    pub fn is_expired_at(&self, now: time::Tm) -> bool {
+        self.effective_expiry(now).map_or(false, |expiry| expiry <= now)
+    }
+
+    /// Returns whether this cookie has expired by now
+    ///
+    /// This is `is_expired_at` using the current time, so a cookie whose
+    /// `expires` is in the past or whose `max_age` is zero has expired. A
+    /// session cookie, with neither attribute, returns `false`.
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(time::now_utc())
+    }
+
+    /// Returns how long this cookie has left to live, counting from now
+    ///
+    /// This is `time_until_expiry` using the current time, so `max_age` takes
+    /// precedence over `expires` as in RFC 6265, and an expired cookie has
+    /// zero time remaining. A session cookie, with neither attribute, returns
+    /// `None`.
+    pub fn time_remaining(&self) -> Option<time::Duration> {
+        self.time_until_expiry(time::now_utc())
+    }
+
+    /// Returns whether this cookie is just a `name=value` pair, without any
+    /// attributes
+    ///
+    /// Such a cookie formats as exactly `name=value`, so callers can skip any
+    /// attribute handling for it. Unrecognized attributes, with or without a
+    /// value, count as attributes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// assert!(Cookie::parse("foo=bar").unwrap().is_simple_pair());
+    /// assert!(!Cookie::parse("foo=bar; Secure").unwrap().is_simple_pair());
+    /// ```
+    pub fn is_simple_pair(&self) -> bool {
+        self.expires.is_none() && self.max_age.is_none() &&
+            self.domain.is_none() && self.path.is_none() &&
+            !self.secure && !self.httponly &&
+            self.same_site.is_none() && self.priority.is_none() &&
+            self.custom.is_empty() && self.flag_attrs.is_empty()
+    }
+
+    /// Returns whether this is a session cookie, with neither `expires` nor
+    /// `max_age`
+    ///
+    /// Browsers discard session cookies when they are closed.
+    pub fn is_session(&self) -> bool {
+        self.expires.is_none() && self.max_age.is_none()
+    }
+
+    /// Turns this into a session cookie by removing both `expires` and
+    /// `max_age`
+    ///
+    /// This suits a "remember me" option being turned off, after which the
+    /// cookie should no longer outlive the browser session.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let mut c = Cookie::parse("foo=bar; Max-Age=3600; Path=/").unwrap();
+    /// assert!(!c.is_session());
+    /// c.downgrade_to_session();
+    /// assert!(c.is_session());
+    /// assert_eq!(c.to_string(), "foo=bar; Path=/");
+    /// ```
+    pub fn downgrade_to_session(&mut self) {
+        self.expires = None;
+        self.max_age = None;
+    }
+
+    /// Returns when this cookie expires, if it was received at the time `now`
+    ///
+    /// A `max_age` takes precedence over `expires`, as in RFC 6265, and is
+    /// counted from `now`. The result is capped at the end of the year 9999,
+    /// the latest date an `Expires` attribute can hold. Session cookies, with
+    /// neither attribute, return `None`.
+    pub fn effective_expiry(&self, now: time::Tm) -> Option<time::Tm> {
+        match (self.max_age, self.expires) {
+            (Some(max_age), _) => {
+                let now = now.to_timespec();
+                let remaining = cmp::max(MAX_EXPIRY_SECS - now.sec, 0) as u64;
+                let secs = cmp::min(max_age, remaining) as i64;
+                Some(time::at_utc(now + time::Duration::seconds(secs)))
+            }
+            (None, Some(expires)) => Some(expires),
+            (None, None) => None,
+        }
+    }
+
+    /// Sets `expires` to `max_age` seconds after `now`, if this cookie has a
+    /// `max_age` but no `expires`
+    ///
+    /// Some old browsers ignore `Max-Age`, so sending both attributes keeps
+    /// such browsers from treating the cookie as a session cookie. The date
+    /// is computed as by `effective_expiry`. Nothing changes if `expires` is
+    /// already set or there is no `max_age`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate cookie;
+    /// extern crate time;
+    ///
+    /// use cookie::Cookie;
+    ///
+    /// # fn main() {
+    /// let mut c = Cookie::parse("foo=bar; Max-Age=60").unwrap();
+    /// c.sync_expires_from_max_age(time::at_utc(time::Timespec::new(0, 0)));
+    /// assert_eq!(c.to_string(),
+    ///            "foo=bar; Max-Age=60; Expires=Thu, 01 Jan 1970 00:01:00 GMT");
+    /// # }
+    /// ```
+    pub fn sync_expires_from_max_age(&mut self, now: time::Tm) {
+        if self.expires.is_none() && self.max_age.is_some() {
+            self.expires = self.effective_expiry(now);
+        }
+    }
+
+    /// Sets `max_age`, returning an error if `secs` is too large to be added
+    /// to a time
+    ///
+    /// Any `max_age` larger than the number of seconds from the epoch to the
+    /// end of the year 9999 is rejected, since it can't describe a real date
+    /// and would overflow time arithmetic.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::u64;
+    /// use cookie::Cookie;
+    ///
+    /// let mut c = Cookie::new("foo".into(), "bar".into());
+    /// assert!(c.try_set_max_age(3600).is_ok());
+    /// assert!(c.try_set_max_age(u64::MAX).is_err());
+    /// assert_eq!(c.max_age, Some(3600));
+    /// ```
+    pub fn try_set_max_age(&mut self, secs: u64) -> Result<(), Error> {
+        if secs > MAX_EXPIRY_SECS as u64 {
+            return Err(Error::InvalidMaxAge(secs.to_string()))
+        }
+        self.max_age = Some(secs);
+        Ok(())
+    }
+
+    /// Sets `expires` from a formatted date, such as an `Expires` attribute
+    ///
+    /// The date is parsed with the same formats `parse` accepts, and an error
+    /// is returned, leaving `expires` untouched, if none of them match. The
+    /// date is stored as a `Tm`, so it is formatted again by `to_string`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let mut c = Cookie::new("foo".into(), "bar".into());
+    /// c.set_raw_expires("Sun, 23 Nov 2014 20:00:00 GMT").unwrap();
+    /// assert_eq!(c.to_string(), "foo=bar; Expires=Sun, 23 Nov 2014 20:00:00 GMT");
+    /// assert!(c.set_raw_expires("tomorrow").is_err());
+    /// ```
+    pub fn set_raw_expires(&mut self, formatted: &str) -> Result<(), Error> {
+        match parse_expires(formatted.trim()) {
+            Some(tm) => {
+                self.expires = Some(tm);
+                Ok(())
+            }
+            None => Err(Error::InvalidDate(formatted.to_string())),
+        }
+    }
+
+    /// Returns how long this cookie has left to live, if it was received at
+    /// the time `now`
+    ///
+    /// The remaining lifetime is measured to `effective_expiry`, and is zero
+    /// for a cookie which has already expired. Session cookies return `None`.
+    pub fn time_until_expiry(&self, now: time::Tm) -> Option<time::Duration> {
+        self.effective_expiry(now).map(|expiry| {
+            cmp::max(expiry - now, time::Duration::zero())
+        })
+    }
+
+    /// Returns whether a browser would send this cookie with a cross-site
+    /// request
+    ///
+    /// `method_is_safe` is whether the request uses a safe method such as
+    /// `GET`, and `top_level_nav` is whether it navigates the top-level
+    /// browsing context. Following current browsers:
+    ///
+    /// * `SameSite=Strict` cookies are never sent cross-site.
+    /// * `SameSite=Lax` cookies are only sent with safe top-level navigations.
+    /// * `SameSite=None` cookies are always sent, although browsers only
+    ///   accept them when they are also `Secure`.
+    /// * Cookies without a `SameSite` attribute are treated as "Lax allowing
+    ///   unsafe", and sent with any top-level navigation, including a `POST`.
+    pub fn sent_on_cross_site(&self, method_is_safe: bool, top_level_nav: bool) -> bool {
+        match self.same_site {
+            Some(SameSite::Strict) => false,
+            Some(SameSite::Lax) => method_is_safe && top_level_nav,
+            Some(SameSite::None) => true,
+            None => top_level_nav,
+        }
+    }
+
+    /// Returns whether this cookie would be sent with a request to `url`
+    ///
+    /// The cookie's domain must match the URL's host as in `matches_domain`,
+    /// its path must match the URL's path as in `matches_path`, and a
+    /// `Secure` cookie is only sent over `https` or `wss`. A host-only cookie
+    /// is assumed to have been set by the URL's host, so any host matches.
+    ///
+    /// As browsers do to ease local development, a `Secure` cookie is also
+    /// sent over plain `http` to `localhost`, its subdomains and loopback
+    /// addresses. This exception never applies to any other host.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate cookie;
+    /// extern crate url;
+    ///
+    /// use cookie::Cookie;
+    /// use url::Url;
+    ///
+    /// # fn main() {
+    /// let c = Cookie::parse("foo=bar; Domain=example.com; Path=/app; Secure").unwrap();
+    /// assert!(c.matches(&Url::parse("https://www.example.com/app/x").unwrap()));
+    /// assert!(!c.matches(&Url::parse("http://www.example.com/app/x").unwrap()));
+    /// # }
+    /// ```
+    pub fn matches(&self, url: &Url) -> bool {
+        let host = match url.host_str() {
+            Some(host) => host,
+            None => return false,
+        };
+        let domain_ok = self.domain.is_none() || self.matches_domain(host);
+        if !domain_ok || !self.matches_path(url.path()) {
+            return false
+        }
+        !self.secure || url.scheme() == "https" || url.scheme() == "wss" ||
+            is_loopback(url)
+    }
+
+    /// Returns whether this cookie's `Path` matches the request path `path`,
+    /// so that it would be sent with the request
+    ///
+    /// Per RFC 6265 Section 5.1.4, the cookie's path must equal `path` or be a
+    /// prefix of it ending at a `/`, so `Path=/foo` matches `/foo` and
+    /// `/foo/bar` but not `/foobar`. A cookie without a `Path` is treated as
+    /// having `Path=/`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse("foo=bar; Path=/foo").unwrap();
+    /// assert!(c.matches_path("/foo/bar"));
+    /// assert!(!c.matches_path("/foobar"));
+    /// ```
+    pub fn matches_path(&self, path: &str) -> bool {
+        let cookie_path = match self.path {
+            Some(ref p) => &p[..],
+            None => "/",
+        };
+        path == cookie_path ||
+            (path.starts_with(cookie_path) &&
+             (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+    }
+
+    /// Returns whether this is a host-only cookie, without a `Domain`
+    ///
+    /// A host-only cookie is only sent back to the exact host which set it.
+    /// Note that setting `Domain` to that same host does not make a cookie
+    /// host-only: the cookie then also matches every subdomain of the host.
+    pub fn is_host_only(&self) -> bool {
+        self.domain.is_none()
+    }
+
+    /// Checks that this cookie meets the requirements of its `__Secure-` or
+    /// `__Host-` name prefix
+    ///
+    /// Browsers drop a cookie named with `__Secure-` unless it is `Secure`,
+    /// and one named with `__Host-` unless it is also `Path=/` and has no
+    /// `Domain`. Returns `Error::PrefixMismatch` if this cookie would be
+    /// dropped. Unlike `CookieJar::try_add`, missing attributes are not
+    /// filled in. Cookies without either prefix are always valid.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse("__Host-id=1; Secure; Path=/").unwrap();
+    /// assert!(c.validate_prefix().is_ok());
+    /// let c = Cookie::parse("__Host-id=1; Secure").unwrap();
+    /// assert!(c.validate_prefix().is_err());
+    /// ```
+    pub fn validate_prefix(&self) -> Result<(), Error> {
+        let valid = if self.name.starts_with("__Host-") {
+            self.secure && self.domain.is_none() &&
+                self.path.as_ref().map_or(false, |p| p == "/")
+        } else if self.name.starts_with("__Secure-") {
+            self.secure
+        } else {
+            true
+        };
+        if valid { Ok(()) } else { Err(Error::PrefixMismatch) }
+    }
+
+    /// Returns whether this cookie meets the requirements of its name prefix,
+    /// see `validate_prefix`
+    pub fn is_valid_prefix(&self) -> bool {
+        self.validate_prefix().is_ok()
+    }
+
+    /// Makes this a host-only cookie by removing its `Domain` attribute
+    ///
+    /// A cookie with a `Domain` is sent to that domain and all of its
+    /// subdomains, while a host-only cookie is only ever sent back to the
+    /// exact host which set it. Narrowing a cookie this way keeps it away from
+    /// sibling subdomains which may be less trustworthy.
+    pub fn make_host_only(&mut self) {
+        self.domain = None;
+    }
+
+    /// Restricts this cookie to requests under `path`
+    ///
+    /// See `try_scope_to_path` for a version which checks that `path` is an
+    /// absolute path.
+    pub fn scope_to_path<S: Into<String>>(&mut self, path: S) {
+        self.path = Some(path.into());
+    }
+
+    /// Restricts this cookie to requests under `path`, returning an error if
+    /// `path` doesn't start with `/`
+    ///
+    /// User agents ignore a `Path` attribute which isn't absolute.
+    pub fn try_scope_to_path<S: Into<String>>(&mut self, path: S) -> Result<(), Error> {
+        let path = path.into();
+        if !path.starts_with('/') {
+            return Err(Error::InvalidPath(path))
+        }
+        self.path = Some(path);
+        Ok(())
+    }
+
+    /// Sorts this cookie's custom attributes by name with the given
+    /// comparison function
+    ///
+    /// Custom attributes are emitted after all of the recognized attributes,
+    /// in insertion order by default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let mut c = Cookie::parse("foo=bar; a=1; Secure; c=3; b=2").unwrap();
+    /// c.sort_custom_by(|a, b| b.cmp(a));
+    /// assert_eq!(c.to_string(), "foo=bar; Secure; c=3; b=2; a=1");
+    /// ```
+    pub fn sort_custom_by<F>(&mut self, cmp: F)
+        where F: FnMut(&str, &str) -> cmp::Ordering
+    {
+        self.custom.sort_by(cmp);
+    }
+
+    /// Lowercases the names of all of this cookie's custom attributes
+    ///
+    /// Attributes whose names only differ in case collapse into one, which
+    /// keeps the position of the first of them and the value of the last.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let mut c = Cookie::parse("foo=bar; Foo=1; foo=2").unwrap();
+    /// c.canonicalize_custom_keys();
+    /// assert_eq!(c.to_string(), "foo=bar; foo=2");
+    /// ```
+    pub fn canonicalize_custom_keys(&mut self) {
+        let custom = ::std::mem::replace(&mut self.custom, CustomAttributes::new());
+        for (k, v) in custom.iter() {
+            self.custom.insert(k.to_ascii_lowercase(), v.clone());
+        }
+    }
+
+    /// Returns the name of this cookie, for use as a key when deduplicating
+    /// cookies by name
+    ///
+    /// `Cookie` compares every field for equality, so two cookies with the
+    /// same name but different values or attributes are distinct in a
+    /// `HashSet<Cookie>`. To keep one cookie per name, store them in a
+    /// `HashMap<String, Cookie>` keyed by this instead. Use `same_key` to
+    /// also tell apart cookies with different domains or paths.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use cookie::Cookie;
+    ///
+    /// let mut by_name = HashMap::new();
+    /// for header in &["a=1", "b=2; Path=/", "a=3; Secure"] {
+    ///     let c = Cookie::parse(header).unwrap();
+    ///     by_name.insert(c.by_name().to_string(), c);
+    /// }
+    /// assert_eq!(by_name.len(), 2);
+    /// assert_eq!(by_name["a"].value, "3");
+    /// ```
+    pub fn by_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns whether this cookie and `other` share the same name, domain and
+    /// path
+    ///
+    /// This is the key RFC 6265 uses to identify a stored cookie, so a cookie
+    /// received with the same key as an existing one replaces it. Domains are
+    /// compared case-insensitively and ignoring any leading dot.
+    pub fn same_key(&self, other: &Cookie) -> bool {
+        fn domain(c: &Cookie) -> Option<String> {
+            c.domain.as_ref().map(|d| {
+                d.trim_left_matches('.').to_ascii_lowercase()
+            })
+        }
+
+        self.name == other.name &&
+            self.path == other.path &&
+            domain(self) == domain(other)
+    }
+
+    /// Returns whether this cookie and `other` share a name but not a key,
+    /// so that a browser could store both and send them together
+    ///
+    /// A request matching both cookies' domains and paths then carries two
+    /// cookies of the same name, and servers which only read the first of
+    /// them may pick up the wrong one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let root = Cookie::parse("sid=1; Path=/").unwrap();
+    /// let admin = Cookie::parse("sid=2; Path=/admin").unwrap();
+    /// assert!(root.could_shadow(&admin));
+    /// ```
+    pub fn could_shadow(&self, other: &Cookie) -> bool {
+        self.name == other.name && !self.same_key(other)
+    }
+
+    /// Returns whether a browser would accept this cookie's `Domain` when it
+    /// is set by a response from `host`
+    ///
+    /// Per RFC 6265, the domain must be `host` itself or one of its parent
+    /// domains, compared case-insensitively and ignoring any leading dot. An
+    /// IP address host only accepts itself. Host-only cookies, without a
+    /// `Domain`, are always accepted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse("foo=bar; Domain=example.com").unwrap();
+    /// assert!(c.domain_valid_for("www.example.com"));
+    /// assert!(!c.domain_valid_for("example.org"));
+    /// ```
+    pub fn domain_valid_for(&self, host: &str) -> bool {
+        self.domain.is_none() || self.matches_domain(host)
+    }
+
+    /// Returns whether this cookie's `Domain` matches `host`, so that it would
+    /// be sent with requests to `host`
+    ///
+    /// This is the domain-matching algorithm of RFC 6265 Section 5.1.3: a
+    /// cookie with a `Domain` is sent to that domain and all of its
+    /// subdomains, compared case-insensitively and ignoring any leading dot.
+    /// If either the host or the `Domain` is an IP address, only an exact
+    /// match counts. A host-only cookie is only sent to the exact host which
+    /// set it, which a `Cookie` doesn't record, so this always returns
+    /// `false` for one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse("foo=bar; Domain=example.com").unwrap();
+    /// assert!(c.matches_domain("example.com"));
+    /// assert!(c.matches_domain("www.example.com"));
+    /// assert!(!c.matches_domain("example.org"));
+    /// ```
+    pub fn matches_domain(&self, host: &str) -> bool {
+        let domain = match self.domain {
+            Some(ref domain) => domain.trim_left_matches('.').to_ascii_lowercase(),
+            None => return false,
+        };
+        let host = host.to_ascii_lowercase();
+        if host == domain {
+            return true
+        }
+        let is_ip = |s: &str| s.starts_with('[') || s.parse::<IpAddr>().is_ok();
+        !is_ip(&host) && !is_ip(&domain) && !domain.is_empty() &&
+            host.ends_with(&domain[..]) &&
+            host[..host.len() - domain.len()].ends_with('.')
+    }
+
+    /// Splits the `Set-Cookie` header value for this `Cookie` into its
+    /// `name=value` pair and its attributes
+    ///
+    /// The attributes are formatted exactly as `to_string` formats them, but
+    /// without the leading `; ` separator.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse("foo=bar; Secure; Path=/").unwrap();
+    /// let (pair, attrs) = c.header_parts();
+    /// assert_eq!(pair, "foo=bar");
+    /// assert_eq!(attrs, "Secure; Path=/");
+    /// ```
+    pub fn header_parts(&self) -> (String, String) {
+        let pair = AttrVal(&self.name, &self.quoted_value()).to_string();
+        let mut attrs = self.to_string().split_off(pair.len());
+        if attrs.starts_with("; ") {
+            attrs = attrs[2..].to_string();
+        }
+        (pair, attrs)
+    }
+
+    /// Formats this `Cookie` as a legacy RFC 2965 `Set-Cookie2` header value
+    ///
+    /// The value and attribute values are quoted strings, `Version="1"` is
+    /// always included, and a `Domain` is given the leading dot RFC 2965
+    /// requires. A cookie without a `max_age` is marked `Discard`, since
+    /// `Set-Cookie2` has no `Expires` attribute. `HttpOnly`, `SameSite`,
+    /// `Priority` and custom attributes have no RFC 2965 equivalent and are
+    /// left out.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse("Customer=WILE_E_COYOTE; Path=/acme; Max-Age=60").unwrap();
+    /// assert_eq!(c.to_set_cookie2(),
+    ///            "Customer=\"WILE_E_COYOTE\"; Version=\"1\"; Path=\"/acme\"; \
+    ///             Max-Age=\"60\"");
+    /// ```
+    pub fn to_set_cookie2(&self) -> String {
+        fn quote(s: &str) -> String {
+            let mut quoted = String::with_capacity(s.len() + 2);
+            quoted.push('"');
+            for ch in s.chars() {
+                if ch == '"' || ch == '\\' {
+                    quoted.push('\\');
+                }
+                quoted.push(ch);
+            }
+            quoted.push('"');
+            quoted
+        }
+
+        let mut out = format!("{}={}; Version=\"1\"", self.name, quote(&self.value));
+        if let Some(ref path) = self.path {
+            out.push_str(&format!("; Path={}", quote(path)));
+        }
+        if let Some(ref domain) = self.domain {
+            let domain = format!(".{}", domain.trim_left_matches('.'));
+            out.push_str(&format!("; Domain={}", quote(&domain)));
+        }
+        match self.max_age {
+            Some(max_age) => out.push_str(&format!("; Max-Age=\"{}\"", max_age)),
+            None => out.push_str("; Discard"),
+        }
+        if self.secure {
+            out.push_str("; Secure");
+        }
+        out
+    }
+
+    /// Returns the name and value of the `Set-Cookie` header for this
+    /// `Cookie`, ready to pass to an HTTP/2 encoder
+    ///
+    /// HTTP/2 requires header field names to be lowercase, so the name is
+    /// always `set-cookie`. The value is formatted as by `to_string`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse("foo=bar; Secure").unwrap();
+    /// assert_eq!(c.to_h2_header(), ("set-cookie", "foo=bar; Secure".to_string()));
+    /// ```
+    pub fn to_h2_header(&self) -> (&'static str, String) {
+        ("set-cookie", self.to_string())
+    }
+
+    /// Returns each attribute of this `Cookie` as a key/value pair
+    ///
+    /// Attributes appear in the same order as `to_string` emits them, with
+    /// flags such as `Secure` given an empty value and the custom attributes
+    /// last.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse("foo=bar; Secure; Path=/").unwrap();
+    /// assert_eq!(c.attribute_pairs(),
+    ///            vec![("Secure".to_string(), "".to_string()),
+    ///                 ("Path".to_string(), "/".to_string())]);
+    /// ```
+    pub fn attribute_pairs(&self) -> Vec<(String, String)> {
+        let mut pairs = Vec::new();
+        for kind in AttributeKind::default_order() {
+            let _ = self.each_attr(*kind, |k, v| {
+                let v = v.map_or(String::new(), |v| v.to_string());
+                pairs.push((k.to_string(), v));
+                Ok(())
+            });
+        }
+        pairs
+    }
+
+    /// Returns a short summary of this cookie's security flags, for logging
+    ///
+    /// `S` stands for `Secure` and `H` for `HttpOnly`, followed by
+    /// `SS:<value>` for a `SameSite` attribute, separated from the flags by a
+    /// space. A cookie with none of these gives an empty string. This is only
+    /// meant to be read by people, never sent in a header.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let c = Cookie::parse("foo=bar; Secure; HttpOnly; SameSite=Lax").unwrap();
+    /// assert_eq!(format!("{} [{}]", c.pair(), c.flags_string()), "foo=bar [SH SS:Lax]");
+    /// ```
+    pub fn flags_string(&self) -> String {
+        let mut flags = String::new();
+        if self.secure {
+            flags.push('S');
+        }
+        if self.httponly {
+            flags.push('H');
+        }
+        if let Some(same_site) = self.same_site {
+            if !flags.is_empty() {
+                flags.push(' ');
+            }
+            flags.push_str(&format!("SS:{}", same_site));
+        }
+        flags
+    }
+
+    /// Returns the fields of this `Cookie` as a map of strings, such as for
+    /// a template engine
+    ///
+    /// The map always has `name`, `value`, `secure` and `http_only`, with the
+    /// flags as `"true"` or `"false"`. It also has `path`, `domain`,
+    /// `expires`, `max_age`, `same_site` and `priority` when they are set,
+    /// formatted as in a `Set-Cookie` header, so `expires` is an RFC 822
+    /// date. Custom attributes aren't included.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::Cookie;
+    ///
+    /// let map = Cookie::parse("foo=bar; Path=/; Secure").unwrap().to_map();
+    /// assert_eq!(map["name"], "foo");
+    /// assert_eq!(map["path"], "/");
+    /// assert_eq!(map["secure"], "true");
+    /// assert!(!map.contains_key("domain"));
+    /// ```
+    pub fn to_map(&self) -> HashMap<String, String> {
+        let mut map = HashMap::new();
+        map.insert("name".to_string(), self.name.clone());
+        map.insert("value".to_string(), self.value.clone());
+        map.insert("secure".to_string(), self.secure.to_string());
+        map.insert("http_only".to_string(), self.httponly.to_string());
+        let fields = [(AttributeKind::Path, "path"),
+                      (AttributeKind::Domain, "domain"),
+                      (AttributeKind::Expires, "expires"),
+                      (AttributeKind::MaxAge, "max_age"),
+                      (AttributeKind::SameSite, "same_site"),
+                      (AttributeKind::Priority, "priority")];
+        for &(kind, field) in fields.iter() {
+            let _ = self.each_attr(kind, |_, v| {
+                if let Some(v) = v {
+                    map.insert(field.to_string(), v.to_string());
+                }
+                Ok(())
+            });
+        }
+        map
+    }
+
+    /// Checks that this `Cookie` can be formatted as a well formed
+    /// `Set-Cookie` header value
+    ///
+    /// Returns an error if the name is empty, if `expires` doesn't describe a
+    /// real date, or if a custom attribute, with or without a value, has the
+    /// name of an attribute with its own field, such as `Path` or `Secure`.
+    /// Such names are compared case-insensitively, and would otherwise be
+    /// emitted alongside, and conflict with, the field's own attribute.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::{Cookie, Error};
+    ///
+    /// let mut c = Cookie::new("foo".to_string(), "bar".to_string());
+    /// assert!(c.validate().is_ok());
+    /// c.custom.insert("path".to_string(), "/".to_string());
+    /// assert_eq!(c.validate(), Err(Error::ReservedAttribute("path".to_string())));
+    /// ```
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.name.is_empty() {
+            return Err(Error::EmptyName)
+        }
+        match self.expires {
+            Some(ref t) if !valid_tm(t) => {
+                return Err(Error::InvalidDate(format!("{:?}", t)))
+            }
+            _ => {}
+        }
+        let custom = self.custom.iter().map(|(k, _)| k);
+        match custom.chain(self.flag_attrs.iter()).find(|k| is_reserved_attr(k)) {
+            Some(k) => Err(Error::ReservedAttribute(k.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the custom attribute `key` to `value`, unless `key` is the name
+    /// of an attribute with its own field
+    ///
+    /// Returns the previous value of `key`, like `CustomAttributes::insert`,
+    /// or `Error::ReservedAttribute` without changing anything if `key` is
+    /// reserved, as checked by `validate`.
+    pub fn try_set_custom<K, V>(&mut self, key: K, value: V) -> Result<Option<String>, Error>
+        where K: Into<String>, V: Into<String>
+    {
+        let key = key.into();
+        if is_reserved_attr(&key) {
+            return Err(Error::ReservedAttribute(key))
+        }
+        Ok(self.custom.insert(key, value.into()))
+    }
+
+    /// Formats this `Cookie` as a `Set-Cookie` header value, checking that
+    /// the result is well formed
+    ///
+    /// Unlike `to_string`, this returns an error rather than emitting a
+    /// nameless cookie, an `Expires` attribute whose `Tm` doesn't describe a
+    /// real date or a custom attribute with a reserved name. See `validate`.
+    pub fn to_string_checked(&self) -> Result<String, Error> {
+        try!(self.validate());
+        Ok(self.to_string())
+    }
+
+    /// Like `to_string_checked`, but also returns an error if the header value
+    /// is longer than `max_bytes`
+    pub fn to_string_checked_within(&self, max_bytes: usize)
+                                    -> Result<String, Error> {
+        let s = try!(self.to_string_checked());
+        if s.len() > max_bytes {
+            return Err(Error::TooLarge)
+        }
+        Ok(s)
+    }
+
+    /// Returns the length in bytes of this cookie's `Set-Cookie` header value,
+    /// including all of its attributes
+    pub fn header_len(&self) -> usize {
+        self.to_string().len()
+    }
+
+    /// Returns whether this cookie's `Set-Cookie` header value is at most
+    /// `max_bytes` long
+    ///
+    /// Unlike `pair_too_large`, this counts every attribute as well as the
+    /// `name=value` pair.
+    pub fn fits_in(&self, max_bytes: usize) -> bool {
+        self.header_len() <= max_bytes
+    }
+
+    /// Returns the length in bytes of this cookie's `name=value` pair
+    pub fn pair_len(&self) -> usize {
+        self.name.len() + 1 + self.quoted_value().len()
+    }
+
+    /// Returns whether this cookie's `name=value` pair is larger than browsers
+    /// are required to store
+    ///
+    /// RFC 6265 only requires user agents to support cookies of up to 4096
+    /// bytes, and most silently drop anything larger.
+    pub fn pair_too_large(&self) -> bool {
+        self.pair_len() > MAX_PAIR_LEN
+    }
+
+    /// Formats this `Cookie` as a `Set-Cookie` header value, emitting its
+    /// attributes in the given order
+    ///
+    /// Some clients are sensitive to the order attributes appear in. Any kind
+    /// of attribute missing from `order` is emitted afterwards, in the same
+    /// order as `to_string`, so no attributes are lost.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cookie::{AttributeKind, Cookie};
+    ///
+    /// let mut c = Cookie::new("foo".into(), "bar".into());
+    /// c.secure = true;
+    /// c.path = Some("/".into());
+    /// c.httponly = true;
+    /// let order = [AttributeKind::Path, AttributeKind::Secure];
+    /// assert_eq!(c.to_header_string_with_order(&order),
+    ///            "foo=bar; Path=/; Secure; HttpOnly");
+    /// ```
+    pub fn to_header_string_with_order(&self, order: &[AttributeKind]) -> String {
+        struct Ordered<'a>(&'a Cookie, &'a [AttributeKind]);
+
+        impl<'a> fmt::Display for Ordered<'a> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                try!(AttrVal(&self.0.name, &self.0.quoted_value()).fmt(f));
+                self.0.fmt_attrs_ordered(f, self.1)
+            }
+        }
+
+        Ordered(self, order).to_string()
+    }
+
+    fn fmt_attrs(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_attrs_ordered(f, AttributeKind::default_order())
+    }
+
+    fn fmt_attrs_ordered(&self, f: &mut fmt::Formatter,
+                         order: &[AttributeKind]) -> fmt::Result {
+        let defaults = AttributeKind::default_order();
+        let rest = defaults.iter().filter(|kind| !order.contains(kind));
+        for (i, kind) in order.iter().chain(rest).enumerate() {
+            if i < order.len() && order[..i].contains(kind) {
+                continue
+            }
+            try!(self.fmt_attr(f, *kind));
+        }
+        Ok(())
+    }
+
+    fn fmt_attr(&self, f: &mut fmt::Formatter, kind: AttributeKind) -> fmt::Result {
+        self.each_attr(kind, |k, v| {
+            match v {
+                Some(v) => write!(f, "; {}={}", k, v),
+                None => write!(f, "; {}", k),
+            }
+        })
+    }
+
+    // Calls `f` with the name and, unless it is a flag, the value of each
+    // attribute of `kind` this cookie has. Every other way of listing the
+    // attributes goes through this, so they're all named and formatted as in
+    // the `Set-Cookie` header.
+    fn each_attr<F>(&self, kind: AttributeKind, mut f: F) -> fmt::Result
+        where F: FnMut(&str, Option<&fmt::Display>) -> fmt::Result
+    {
+        match kind {
+            AttributeKind::HttpOnly => {
+                if self.httponly { try!(f("HttpOnly", None)); }
+            }
+            AttributeKind::Secure => {
+                if self.secure { try!(f("Secure", None)); }
+            }
+            AttributeKind::SameSite => {
+                if let Some(ref same_site) = self.same_site {
+                    try!(f("SameSite", Some(same_site)));
+                }
+            }
+            AttributeKind::Path => {
+                if let Some(ref s) = self.path {
+                    try!(f("Path", Some(s)));
+                }
+            }
+            AttributeKind::Domain => {
+                if let Some(ref s) = self.domain {
+                    try!(f("Domain", Some(s)));
+                }
+            }
+            AttributeKind::MaxAge => {
+                if let Some(ref n) = self.max_age {
+                    try!(f("Max-Age", Some(n)));
+                }
+            }
+            AttributeKind::Expires => {
+                if let Some(ref t) = self.expires {
+                    try!(f("Expires", Some(&t.rfc822())));
+                }
+            }
+            AttributeKind::Priority => {
+                if let Some(ref priority) = self.priority {
+                    try!(f("Priority", Some(priority)));
+                }
+            }
+            AttributeKind::Custom => {
+                for flag in self.flag_attrs.iter() {
+                    try!(f(flag, None));
+                }
+                for (k, v) in self.custom.iter() {
+                    try!(f(k, Some(v)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+const MAX_PAIR_LEN: usize = 4096;
+
+// The attributes `parse` stores in their own fields, in lowercase.
+const RESERVED_ATTRS: &'static [&'static str] = &["secure", "httponly", "max-age", "domain",
+                                                  "path", "samesite", "priority", "expires"];
+
+fn is_reserved_attr(name: &str) -> bool {
+    RESERVED_ATTRS.iter().any(|attr| name.eq_ignore_ascii_case(attr))
+}
+
+// 9999-12-31 23:59:59 UTC, in seconds since the epoch.
+const MAX_EXPIRY_SECS: i64 = 253402300799;
+
+fn parse_expires(v: &str) -> Option<time::Tm> {
+    // Some servers delete cookies with `Expires=0` or a negative number rather
+    // than a date, so treat those as having expired at the epoch.
+    match v.parse::<i64>() {
+        Ok(n) if n <= 0 => return Some(time::at_utc(time::Timespec::new(0, 0))),
+        Ok(_) => return None,
+        Err(_) => {}
+    }
+
+    // `%d` accepts a day without zero padding, but asctime pads single-digit
+    // days with a space instead (`Jun  9`), so collapse runs of whitespace.
+    let collapsed;
+    let v = if v.contains("  ") {
+        collapsed = v.split_whitespace().collect::<Vec<_>>().join(" ");
+        &collapsed[..]
+    } else {
+        v
+    };
+
+    // Try strptime with three date formats according to
+    // http://tools.ietf.org/html/rfc2616#section-3.3.1
+    // Try additional ones as encountered in the real world.
+    // `%Y` needs all four digits, while `%y` always lands in the 1900s, so
+    // undo that and leave two-digit years to `normalize_year`.
+    let short_year = |format| {
+        time::strptime(v, format).map(|mut tm| {
+            tm.tm_year -= 1900;
+            tm
+        })
+    };
+    // Numeric offsets such as `+0000` or `-0700` must be tried first, as `%Z`
+    // accepts them too but silently ignores the offset.
+    let tm = time::strptime(v, "%a, %d %b %Y %H:%M:%S %z").or_else(|_| {
+        time::strptime(v, "%a, %d-%b-%Y %H:%M:%S %z")
+    }).or_else(|_| {
+        time::strptime(v, "%a, %d %b %Y %H:%M:%S %Z")
+    }).or_else(|_| {
+        short_year("%A, %d-%b-%y %H:%M:%S %Z")
+    }).or_else(|_| {
+        time::strptime(v, "%a, %d-%b-%Y %H:%M:%S %Z")
+    }).or_else(|_| {
+        time::strptime(v, "%a %b %d %H:%M:%S %Y")
+    }).or_else(|_| {
+        short_year("%a, %d %b %y %H:%M:%S %Z")
+    }).or_else(|_| {
+        short_year("%a, %d-%b-%y %H:%M:%S %Z")
+    });
+    tm.ok().map(normalize_year).map(to_utc)
+}
+
+// `Tm::to_utc` goes through the local time zone rather than `tm_utcoff`, so
+// apply the offset by hand.
+fn to_utc(mut tm: time::Tm) -> time::Tm {
+    if tm.tm_utcoff == 0 {
+        return tm
+    }
+    let offset = time::Duration::seconds(tm.tm_utcoff as i64);
+    tm.tm_utcoff = 0;
+    time::at_utc(tm.to_timespec() - offset)
+}
+
+// Whether a date has a word which isn't an English day or month name, full or
+// abbreviated, or a time zone such as `GMT`.
+fn is_localized_date(v: &str) -> bool {
+    const ENGLISH: &'static [&'static str] = &[
+        "monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday",
+        "january", "february", "march", "april", "may", "june", "july", "august",
+        "september", "october", "november", "december", "gmt", "utc", "ut",
+    ];
+    v.split(|c: char| !c.is_alphabetic()).filter(|w| !w.is_empty()).any(|word| {
+        let word = word.to_lowercase();
+        !ENGLISH.iter().any(|name| {
+            *name == word || (word.len() == 3 && name.starts_with(&word[..]))
+        })
+    })
+}
+
+// RFC 6265 section 5.1.1: a two-digit year of 70 to 99 means 1970 to 1999,
+// and one of 0 to 69 means 2000 to 2069.
+fn normalize_year(mut tm: time::Tm) -> time::Tm {
+    let year = tm.tm_year + 1900;
+    if year >= 0 && year < 70 {
+        tm.tm_year = year + 100;
+    } else if year >= 70 && year < 100 {
+        tm.tm_year = year;
+    }
+    tm
+}
+
+fn is_loopback(url: &Url) -> bool {
+    match url.host() {
+        Some(url::Host::Domain(d)) => {
+            let d = d.to_ascii_lowercase();
+            d == "localhost" || d.ends_with(".localhost")
+        }
+        Some(url::Host::Ipv4(addr)) => addr.is_loopback(),
+        Some(url::Host::Ipv6(addr)) => addr.is_loopback(),
+        None => false,
+    }
+}
+
+// Cookie domains never include a port, but some servers send one anyway.
+fn strip_port(domain: &str) -> &str {
+    let pos = match domain.rfind(':') {
+        Some(pos) => pos,
+        None => return domain,
+    };
+    let (host, port) = (&domain[..pos], &domain[pos + 1..]);
+    let is_port = !port.is_empty() && port.bytes().all(|b| b >= b'0' && b <= b'9');
+    // Don't mistake the last segment of a bare IPv6 address for a port.
+    if is_port && (!host.contains(':') || host.ends_with(']')) {
+        host
+    } else {
+        domain
+    }
+}
+
+fn trim_header(s: &str) -> &str {
+    // Some clients prefix the header with a UTF-8 BOM or stray control
+    // characters, neither of which can be part of a cookie name.
+    s.trim_left_matches(|c: char| c == '\u{feff}' || c.is_control()).trim()
+}
+
+// Checks that every field used when formatting `Expires` is in range, and that
+// the year is one RFC 6265 user agents will accept.
+fn valid_tm(tm: &time::Tm) -> bool {
+    tm.tm_sec >= 0 && tm.tm_sec <= 60 &&
+        tm.tm_min >= 0 && tm.tm_min <= 59 &&
+        tm.tm_hour >= 0 && tm.tm_hour <= 23 &&
+        tm.tm_mday >= 1 && tm.tm_mday <= 31 &&
+        tm.tm_mon >= 0 && tm.tm_mon <= 11 &&
+        tm.tm_wday >= 0 && tm.tm_wday <= 6 &&
+        tm.tm_year >= 1601 - 1900 && tm.tm_year <= 9999 - 1900
+}
+
+#[cfg(feature = "serialize-serde")]
+impl Serialize for Cookie {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer
+    {
+        serializer.serialize_str(&*self.to_string())
+    }
+}
+
+#[cfg(feature = "serialize-serde")]
+impl Deserialize for Cookie {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Cookie, D::Error>
+        where D: serde::Deserializer
+    {
+        deserializer.deserialize_string(CookieVisitor)
+    }
+}
+#[cfg(feature = "serialize-serde")]
+struct CookieVisitor;
+
+/// A `Cookie` which serializes as a structure with a member for each field,
+/// rather than as a `Set-Cookie` header string
+///
+/// `Cookie` itself serializes to the string `to_string` returns and parses it
+/// back, which can't represent every cookie, such as one with a custom
+/// attribute value containing `;`. This serializes the same object that
+/// `Cookie::to_json` returns, and deserializes with `Cookie::from_json`.
+///
+/// # Example
+///
+/// ```
+/// extern crate cookie;
+/// extern crate serde_json;
+///
+/// use cookie::{Cookie, StructuredCookie};
+///
+/// # fn main() {
+/// let c = Cookie::parse("foo=bar; Secure").unwrap();
+/// let json = serde_json::to_string(&StructuredCookie(c.clone())).unwrap();
+/// assert_eq!(json, r#"{"httponly":false,"name":"foo","secure":true,"value":"bar"}"#);
+/// let StructuredCookie(roundtrip) = serde_json::from_str(&json).unwrap();
+/// assert_eq!(roundtrip, c);
+/// # }
+/// ```
+#[cfg(feature = "serialize-serde")]
+#[derive(PartialEq, Clone, Debug)]
+pub struct StructuredCookie(pub Cookie);
+
+#[cfg(feature = "serialize-serde")]
+impl Serialize for StructuredCookie {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer
+    {
+        self.0.to_json().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serialize-serde")]
+impl Deserialize for StructuredCookie {
+    fn deserialize<D>(deserializer: &mut D) -> Result<StructuredCookie, D::Error>
+        where D: serde::Deserializer
+    {
+        let json = try!(serde_json::Value::deserialize(deserializer));
+        Cookie::from_json(&json).map(StructuredCookie).map_err(|e| {
+            serde::de::Error::custom(e.to_string())
+        })
+    }
+}
+
+#[cfg(feature = "serialize-serde")]
+impl Cookie {
+    /// Builds a `Cookie` from a JSON object
+    ///
+    /// The object must have string `name` and `value` members. Any of the
+    /// other fields of `Cookie` may also be given under the same names:
+    /// `expires` as a date string or as an object of `secs` and `nsecs` since
+    /// the epoch, `max_age` as a number, `secure` and `httponly` as booleans,
+    /// `custom` as an object of strings, `flag_attrs` as an array of strings,
+    /// and the rest as strings. Members which are `null` or unknown are
+    /// ignored, while members of the wrong type are an error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate cookie;
+    /// extern crate serde_json;
+    ///
+    /// use cookie::Cookie;
+    ///
+    /// # fn main() {
+    /// let json = r#"{"name": "foo", "value": "bar", "secure": true}"#;
+    /// let value: serde_json::Value = serde_json::from_str(json).unwrap();
+    /// let c = Cookie::from_json(&value).unwrap();
+    /// assert_eq!(c.to_string(), "foo=bar; Secure");
+    /// # }
+    /// ```
+    pub fn from_json(json: &serde_json::Value) -> Result<Cookie, Error> {
+        fn invalid(member: &str) -> Error {
+            Error::InvalidJson(member.to_string())
+        }
+        fn string(member: &str, v: Option<&serde_json::Value>) -> Result<String, Error> {
+            v.and_then(|v| v.as_str()).map(|s| s.to_string()).ok_or(invalid(member))
+        }
+        fn boolean(member: &str, v: &serde_json::Value) -> Result<bool, Error> {
+            v.as_bool().ok_or(invalid(member))
+        }
+
+        let obj = try!(json.as_object().ok_or(invalid("")));
+        let name = try!(string("name", obj.get("name")));
+        let value = try!(string("value", obj.get("value")));
+        if name.is_empty() {
+            return Err(Error::EmptyName)
+        }
+
+        let mut c = Cookie::new(name, value);
+        for (k, v) in obj.iter() {
+            if v.is_null() {
+                continue
+            }
+            match &k[..] {
+                "expires" if v.is_object() => {
+                    let secs = try!(v.find("secs").and_then(|v| v.as_i64())
+                                     .ok_or(invalid(k)));
+                    let nsecs = match v.find("nsecs") {
+                        Some(n) => try!(n.as_u64().ok_or(invalid(k))),
+                        None => 0,
+                    };
+                    if nsecs >= 1_000_000_000 {
+                        return Err(invalid(k))
+                    }
+                    let spec = time::Timespec::new(secs, nsecs as i32);
+                    c.expires = Some(time::at_utc(spec));
+                }
+                "expires" => {
+                    let expires = try!(string(k, Some(v)));
+                    match parse_expires(&expires) {
+                        Some(tm) => c.expires = Some(tm),
+                        None => return Err(Error::InvalidDate(expires)),
+                    }
+                }
+                "max_age" => {
+                    c.max_age = match (v.as_u64(), v.as_i64()) {
+                        (Some(n), _) => Some(n),
+                        (None, Some(_)) => Some(0),
+                        (None, None) => return Err(Error::InvalidMaxAge(v.to_string())),
+                    };
+                }
+                "domain" => c.domain = Some(try!(string(k, Some(v)))),
+                "path" => c.path = Some(try!(string(k, Some(v)))),
+                "secure" => c.secure = try!(boolean(k, v)),
+                "httponly" => c.httponly = try!(boolean(k, v)),
+                "same_site" => {
+                    let same_site = try!(string(k, Some(v)));
+                    c.same_site = Some(try!(SameSite::parse(&same_site).ok_or(invalid(k))));
+                }
+                "priority" => {
+                    let priority = try!(string(k, Some(v)));
+                    c.priority = Some(try!(Priority::parse(&priority).ok_or(invalid(k))));
+                }
+                "custom" => {
+                    for (name, v) in try!(v.as_object().ok_or(invalid(k))).iter() {
+                        c.custom.insert(name.clone(), try!(string(k, Some(v))));
+                    }
+                }
+                "flag_attrs" => {
+                    for v in try!(v.as_array().ok_or(invalid(k))).iter() {
+                        c.flag_attrs.push(try!(string(k, Some(v))));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(c)
+    }
+
+    /// Converts this `Cookie` to a JSON object
+    ///
+    /// This is the inverse of `from_json`: the object has a member for each
+    /// field, under the field's name, and `Cookie::from_json` returns this
+    /// cookie again. `name`, `value`, `secure` and `httponly` are always
+    /// present, while fields which are `None` or empty are left out.
+    /// `expires` is an object holding the whole `secs` and the `nsecs` since
+    /// the epoch, rather than a date string, so no precision is lost and no
+    /// date format needs to be parsed back.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate cookie;
+    /// extern crate serde_json;
+    ///
+    /// use cookie::Cookie;
+    ///
+    /// # fn main() {
+    /// let c = Cookie::parse("foo=bar; Max-Age=60").unwrap();
+    /// let json = c.to_json();
+    /// assert_eq!(json.find("max_age").and_then(|v| v.as_u64()), Some(60));
+    /// assert_eq!(Cookie::from_json(&json).unwrap(), c);
+    /// # }
+    /// ```
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::Value;
+
+        fn string(s: &str) -> Value {
+            Value::String(s.to_string())
+        }
+
+        let mut obj = serde_json::Map::new();
+        obj.insert("name".to_string(), string(&self.name));
+        obj.insert("value".to_string(), string(&self.value));
+        if let Some(ref expires) = self.expires {
+            let spec = expires.to_timespec();
+            let mut timestamp = serde_json::Map::new();
+            timestamp.insert("secs".to_string(), Value::I64(spec.sec));
+            timestamp.insert("nsecs".to_string(), Value::U64(spec.nsec as u64));
+            obj.insert("expires".to_string(), Value::Object(timestamp));
+        }
+        if let Some(max_age) = self.max_age {
+            obj.insert("max_age".to_string(), Value::U64(max_age));
+        }
+        let fields = [(AttributeKind::Domain, "domain"),
+                      (AttributeKind::Path, "path"),
+                      (AttributeKind::SameSite, "same_site"),
+                      (AttributeKind::Priority, "priority")];
+        for &(kind, field) in fields.iter() {
+            let _ = self.each_attr(kind, |_, v| {
+                if let Some(v) = v {
+                    obj.insert(field.to_string(), Value::String(v.to_string()));
+                }
+                Ok(())
+            });
+        }
+        obj.insert("secure".to_string(), Value::Bool(self.secure));
+        obj.insert("httponly".to_string(), Value::Bool(self.httponly));
+        if !self.custom.is_empty() {
+            let custom = self.custom.iter().map(|(k, v)| (k.clone(), string(v))).collect();
+            obj.insert("custom".to_string(), Value::Object(custom));
+        }
+        if !self.flag_attrs.is_empty() {
+            let flags = self.flag_attrs.iter().map(|f| string(f)).collect();
+            obj.insert("flag_attrs".to_string(), Value::Array(flags));
+        }
+        Value::Object(obj)
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl Cookie {
+    /// Returns `expires` as a `chrono` date and time in UTC
+    ///
+    /// The result is the same instant as `expires`, down to the nanosecond.
+    /// Returns `None` if there is no `expires`, or if it is outside the range
+    /// `chrono` can represent.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate chrono;
+    /// extern crate cookie;
+    ///
+    /// use chrono::{Datelike, Timelike};
+    /// use cookie::Cookie;
+    ///
+    /// # fn main() {
+    /// let c = Cookie::parse("foo=bar; Expires=Sun, 23 Nov 2014 20:00:00 GMT").unwrap();
+    /// let expires = c.expires_chrono().unwrap();
+    /// assert_eq!((expires.year(), expires.month(), expires.day()), (2014, 11, 23));
+    /// assert_eq!(expires.hour(), 20);
+    /// # }
+    /// ```
+    pub fn expires_chrono(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        use chrono::TimeZone;
+
+        self.expires.and_then(|tm| {
+            let ts = tm.to_timespec();
+            chrono::Utc.timestamp_opt(ts.sec, ts.nsec as u32).single()
+        })
+    }
+
+    /// Sets `expires` from a `chrono` date and time in UTC
+    ///
+    /// `expires` is set to the same instant, so `expires_chrono` returns
+    /// `expires` unchanged.
+    pub fn set_expires_chrono(&mut self, expires: chrono::DateTime<chrono::Utc>) {
+        let ts = time::Timespec::new(expires.timestamp(),
+                                     expires.timestamp_subsec_nanos() as i32);
+        self.expires = Some(time::at_utc(ts));
+    }
+}
+
+#[cfg(feature = "serialize-serde")]
+impl serde::de::Visitor for CookieVisitor {
+    type Value = Cookie;
+
+    fn visit_str<E>(&mut self, v: &str) -> Result<Cookie, E>
+        where E: serde::de::Error
+    {
+        match Cookie::parse(v) {
+            Ok(cookie) => Ok(cookie),
+            Err(_) => Err(serde::de::Error::custom("Could not parse serialized cookie!"))
+        }
+    }
+}
+
+/// The kinds of attribute a `Cookie` can emit in a `Set-Cookie` header
+///
+/// Used with `Cookie::to_header_string_with_order` to control the order in
+/// which attributes are written.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum AttributeKind {
+    /// The `HttpOnly` flag
+    HttpOnly,
+    /// The `Secure` flag
+    Secure,
+    /// The `SameSite` attribute
+    SameSite,
+    /// The `Path` attribute
+    Path,
+    /// The `Domain` attribute
+    Domain,
+    /// The `Max-Age` attribute
+    MaxAge,
+    /// The `Expires` attribute
+    Expires,
+    /// The `Priority` attribute
+    Priority,
+    /// All of the attributes in `Cookie::custom`
+    Custom,
+}
+
+static DEFAULT_ORDER: &'static [AttributeKind] = &[
+    AttributeKind::HttpOnly,
+    AttributeKind::Secure,
+    AttributeKind::SameSite,
+    AttributeKind::Path,
+    AttributeKind::Domain,
+    AttributeKind::MaxAge,
+    AttributeKind::Expires,
+    AttributeKind::Priority,
+    AttributeKind::Custom,
+];
+
+impl AttributeKind {
+    /// Returns the order in which attributes are emitted by `Cookie`'s
+    /// `Display` impl
+    pub fn default_order() -> &'static [AttributeKind] {
+        DEFAULT_ORDER
+    }
+}
+
+/// A `Cookie` whose name and value are percent-encoded when displayed
+///
+/// Created by `Cookie::encoded`. Any byte which isn't allowed in a cookie
+/// value by RFC 6265, along with `%` and `=`, is encoded so that the result
+/// can be decoded again by `Cookie::parse_encoded`.
+pub struct EncodedCookie<'a> {
+    cookie: &'a Cookie,
+}
+
+impl<'a> fmt::Display for EncodedCookie<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = percent_encode(self.cookie.name.as_bytes(), COOKIE_ENCODE_SET);
+        let value = percent_encode(self.cookie.value.as_bytes(), COOKIE_ENCODE_SET);
+        try!(write!(f, "{}={}", name, value));
+        self.cookie.fmt_attrs(f)
+    }
+}
+
+#[derive(Clone)]
+#[allow(non_camel_case_types)]
+struct COOKIE_ENCODE_SET;
+
+impl EncodeSet for COOKIE_ENCODE_SET {
+    fn contains(&self, byte: u8) -> bool {
+        byte == b'%' || byte == b'=' || !is_cookie_octet(byte)
+    }
+}
+
+/// Lazily parses each `Set-Cookie` header value yielded by `iter`
+///
+/// Each item is parsed with `Cookie::parse` only when the returned iterator is
+/// advanced, so this composes with iterators over large inputs such as the
+/// lines of a log file.
+///
+/// # Example
+///
+/// ```
+/// let headers = vec!["a=1; Path=/", "b=2", "=bad"];
+/// let cookies = cookie::parse_iter(headers).collect::<Vec<_>>();
+/// assert_eq!(cookies.len(), 3);
+/// assert_eq!(cookies[0].as_ref().unwrap().name, "a");
+/// assert!(cookies[2].is_err());
+/// ```
+pub fn parse_iter<I>(iter: I) -> ParseIter<I::IntoIter>
+    where I: IntoIterator, I::Item: AsRef<str>
+{
+    ParseIter { iter: iter.into_iter() }
+}
+
+/// Iterator returned by `parse_iter`
+pub struct ParseIter<I> {
+    iter: I,
+}
+
+impl<I> Iterator for ParseIter<I> where I: Iterator, I::Item: AsRef<str> {
+    type Item = Result<Cookie, Error>;
+
+    fn next(&mut self) -> Option<Result<Cookie, Error>> {
+        self.iter.next().map(|s| Cookie::parse(s.as_ref()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+/// Returns whether `s` is a valid cookie name
+///
+/// RFC 6265 requires a cookie name to be a non-empty token, which excludes
+/// control characters, whitespace, non-ASCII characters and the separators
+/// `()<>@,;:\"/[]?={}`.
+///
+/// # Example
+///
+/// ```
+/// assert!(cookie::is_valid_name("session_id"));
+/// assert!(!cookie::is_valid_name("session id"));
+/// ```
+pub fn is_valid_name(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| {
+        match b {
+            b'(' | b')' | b'<' | b'>' | b'@' | b',' | b';' | b':' | b'\\' |
+            b'"' | b'/' | b'[' | b']' | b'?' | b'=' | b'{' | b'}' => false,
+            _ => b > 0x20 && b < 0x7f,
+        }
+    })
+}
+
+/// Returns whether `s` is a valid cookie value
+///
+/// RFC 6265 allows a cookie value to be made of cookie-octets, optionally
+/// wrapped in double quotes. Cookie-octets exclude control characters,
+/// whitespace, non-ASCII characters, `"`, `,`, `;` and `\`.
+///
+/// # Example
+///
+/// ```
+/// assert!(cookie::is_valid_value("abc123"));
+/// assert!(cookie::is_valid_value("\"abc123\""));
+/// assert!(!cookie::is_valid_value("a;b"));
+/// ```
+pub fn is_valid_value(s: &str) -> bool {
+    let s = if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        &s[1..s.len() - 1]
+    } else {
+        s
+    };
+    s.bytes().all(is_cookie_octet)
+}
+
+fn is_cookie_octet(b: u8) -> bool {
+    match b {
+        b'"' | b',' | b';' | b'\\' => false,
+        _ => b > 0x20 && b < 0x7f,
+    }
+}
+
+/// Represents a key/value pair
+pub struct AttrVal<'a>(pub &'a str, pub &'a str);
+
+impl<'a> fmt::Display for AttrVal<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let AttrVal(ref attr, ref val) = *self;
+        write!(f, "{}={}", attr, val)
+    }
+}
 
 impl fmt::Display for Cookie {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        try!(AttrVal(&self.name, &self.value).fmt(f));
-        if self.httponly { try!(write!(f, "; HttpOnly")); }
-        if self.secure { try!(write!(f, "; Secure")); }
-        match self.path {
-            Some(ref s) => try!(write!(f, "; Path={}", s)),
-            None => {}
+        try!(AttrVal(&self.name, &self.quoted_value()).fmt(f));
+        self.fmt_attrs(f)
+    }
+}
+
+impl FromStr for Cookie {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Cookie, Error> {
+        Cookie::parse(s)
+    }
+}
+
+impl From<(String, String)> for Cookie {
+    fn from((name, value): (String, String)) -> Cookie {
+        Cookie::new(name, value)
+    }
+}
+
+impl<'a, 'b> From<(&'a str, &'b str)> for Cookie {
+    fn from((name, value): (&'a str, &'b str)) -> Cookie {
+        Cookie::new(name.to_string(), value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cookie;
+
+    #[test]
+    fn parse() {
+        assert!(Cookie::parse("bar").is_err());
+        assert!(Cookie::parse("=bar").is_err());
+        assert!(Cookie::parse(" =bar").is_err());
+        assert!(Cookie::parse("foo=").is_ok());
+        let mut expected = Cookie::new("foo".to_string(), "bar".to_string());
+        assert_eq!(Cookie::parse("foo=bar").ok().unwrap(), expected);
+        assert_eq!(Cookie::parse("foo = bar").ok().unwrap(), expected);
+        assert_eq!(Cookie::parse(" foo=bar ").ok().unwrap(), expected);
+        assert_eq!(Cookie::parse(" foo=bar ;Domain=").ok().unwrap(), expected);
+        assert_eq!(Cookie::parse(" foo=bar ;Domain= ").ok().unwrap(), expected);
+        assert_eq!(Cookie::parse(" foo=bar ;;").ok().unwrap(), expected);
+        assert_eq!(Cookie::parse(" foo=bar ;Ignored").ok().unwrap().flag_attrs,
+                   vec!["Ignored".to_string()]);
+        expected.httponly = true;
+        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly").ok().unwrap(), expected);
+        assert_eq!(Cookie::parse(" foo=bar ;httponly").ok().unwrap(), expected);
+        assert_eq!(Cookie::parse(" foo=bar ;HTTPONLY=whatever").ok().unwrap(), expected);
+        expected.flag_attrs.push("sekure".to_string());
+        assert_eq!(Cookie::parse(" foo=bar ; sekure; HTTPONLY").ok().unwrap(), expected);
+        expected.flag_attrs.clear();
+        expected.secure = true;
+        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure").ok().unwrap(), expected);
+        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure=aaaa").ok().unwrap(), expected);
+        expected.max_age = Some(0);
+        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
+                                  Max-Age=0").ok().unwrap(), expected);
+        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
+                                  Max-Age = 0 ").ok().unwrap(), expected);
+        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
+                                  Max-Age=-1").ok().unwrap(), expected);
+        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
+                                  Max-Age = -1 ").ok().unwrap(), expected);
+        expected.max_age = Some(4);
+        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
+                                  Max-Age=4").ok().unwrap(), expected);
+        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
+                                  Max-Age = 4 ").ok().unwrap(), expected);
+        expected.path = Some("/foo".to_string());
+        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
+                                  Max-Age=4; Path=/foo").ok().unwrap(), expected);
+        expected.domain = Some("foo.com".to_string());
+        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
+                                  Max-Age=4; Path=/foo; \
+                                  Domain=foo.com").ok().unwrap(), expected);
+        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
+                                  Max-Age=4; Path=/foo; \
+                                  Domain=FOO.COM").ok().unwrap(), expected);
+        expected.custom.insert("wut".to_string(), "lol".to_string());
+        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
+                                  Max-Age=4; Path=/foo; \
+                                  Domain=foo.com; wut=lol").ok().unwrap(), expected);
+
+        assert_eq!(expected.to_string(),
+                   "foo=bar; HttpOnly; Secure; Path=/foo; Domain=foo.com; \
+                    Max-Age=4; wut=lol");
+    }
+
+    #[test]
+    fn cookie_parse_error() {
+        match Cookie::parse("bar") {
+            Ok(_) => assert!(false),
+            Err(_) => assert!(true),
+        }
+    }
+
+    #[test]
+    fn leading_bom() {
+        let expected = Cookie::new("foo".to_string(), "bar".to_string());
+        assert_eq!(Cookie::parse("\u{feff}foo=bar").unwrap(), expected);
+        assert_eq!(Cookie::parse("\u{feff} foo=bar").unwrap(), expected);
+        assert_eq!(Cookie::parse("\x00\x1b\r\nfoo=bar").unwrap(), expected);
+        assert!(Cookie::parse("\u{feff}").is_err());
+        assert!(Cookie::parse("\u{feff}=bar").is_err());
+    }
+
+    #[test]
+    fn trimming() {
+        let mut expected = Cookie::new("foo".to_string(), "bar".to_string());
+        assert_eq!(Cookie::parse("\tfoo=bar\n").unwrap(), expected);
+        assert_eq!(Cookie::parse("\r\n foo\t=\tbar \r\n").unwrap(), expected);
+        assert_eq!(Cookie::parse_no_trim("foo=bar").unwrap(), expected);
+        assert_eq!(Cookie::parse_no_trim("foo\t=\tbar").unwrap(), expected);
+        assert!(Cookie::parse_no_trim("\u{feff}foo=bar").unwrap().name != "foo");
+        assert!(Cookie::parse_no_trim("\t=bar").is_err());
+
+        expected.path = Some("/".to_string());
+        assert_eq!(Cookie::parse("foo=bar;\tPath=/\n").unwrap(), expected);
+        assert_eq!(Cookie::parse_no_trim("foo=bar;\tPath\t=\t/").unwrap(), expected);
+    }
+
+    #[test]
+    fn numeric_expires() {
+        use time;
+
+        let epoch = time::at_utc(time::Timespec::new(0, 0));
+        for header in &["foo=bar; Expires=0", "foo=bar; Expires=-1",
+                        "foo=bar; expires = -3600"] {
+            let cookie = Cookie::parse(header).unwrap();
+            assert_eq!(cookie.expires, Some(epoch));
+            assert!(cookie.expires.unwrap() < time::now_utc());
+        }
+        assert_eq!(Cookie::parse("foo=bar; Expires=1").unwrap().expires, None);
+    }
+
+    #[test]
+    fn single_digit_day_expires() {
+        let pairs = [("Wed, 09 Jun 2021 10:18:14 GMT", "Wed, 9 Jun 2021 10:18:14 GMT"),
+                     ("Wednesday, 09-Jun-21 10:18:14 GMT", "Wednesday, 9-Jun-21 10:18:14 GMT"),
+                     ("Wed, 09-Jun-2021 10:18:14 GMT", "Wed, 9-Jun-2021 10:18:14 GMT"),
+                     ("Wed Jun 09 10:18:14 2021", "Wed Jun  9 10:18:14 2021")];
+        for &(padded, unpadded) in pairs.iter() {
+            let a = Cookie::parse(&format!("foo=bar; Expires={}", padded)).unwrap();
+            let b = Cookie::parse(&format!("foo=bar; Expires={}", unpadded)).unwrap();
+            assert!(a.expires.is_some(), "{}", padded);
+            assert_eq!(a.expires.map(|t| t.to_timespec()),
+                       b.expires.map(|t| t.to_timespec()), "{}", unpadded);
+        }
+    }
+
+    #[test]
+    fn parse_best_effort() {
+        let header = "Set-Cookie: a=1; Path=/, b=2; Expires=Wed, 09 Jun 2021 10:18:14 GMT,\
+                      c=3\x07; HttpOnly, d=5;;; Secure\n\
+                      \n\
+                      garbage\n\
+                      =4\n\
+                      SET-COOKIE:e=6";
+        let cookies = Cookie::parse_best_effort(header);
+        let names = cookies.iter().map(|c| &c.name[..]).collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b", "c", "d", "e"]);
+        assert_eq!(cookies[0].path, Some("/".to_string()));
+        assert!(cookies[1].expires.is_some());
+        assert_eq!(cookies[2].value, "3");
+        assert!(cookies[2].httponly);
+        assert!(cookies[3].secure);
+
+        assert!(Cookie::parse_best_effort("").is_empty());
+        assert!(Cookie::parse_best_effort("nonsense").is_empty());
+    }
+
+    #[test]
+    fn offset_expires() {
+        use time;
+
+        let utc = Cookie::parse("foo=bar; Expires=Wed, 21 Oct 2025 07:28:00 GMT").unwrap();
+        let expected = utc.expires.unwrap();
+        for header in &["foo=bar; Expires=Wed, 21 Oct 2025 07:28:00 +0000",
+                        "foo=bar; Expires=Wed, 21 Oct 2025 00:28:00 -0700",
+                        "foo=bar; Expires=Wed, 21-Oct-2025 09:28:00 +0200",
+                        "foo=bar; Expires=Wed, 21 Oct 2025 00:28:00 -07:00"] {
+            let cookie = Cookie::parse(header).unwrap();
+            let expires = cookie.expires.expect(header);
+            assert_eq!(expires.tm_utcoff, 0, "{}", header);
+            assert_eq!(expires.to_timespec(), expected.to_timespec(), "{}", header);
+            assert_eq!(expires.tm_hour, 7, "{}", header);
+        }
+
+        let cookie = Cookie::parse("foo=bar; Expires=Wed, 21 Oct 2015 00:28:00 -0700").unwrap();
+        assert!(cookie.is_expired_at(time::now_utc()));
+    }
+
+    #[test]
+    fn parse_normalized() {
+        fn changed(s: &str) -> bool {
+            Cookie::parse_normalized(s).unwrap().1
+        }
+
+        let (c, changed_flag) = Cookie::parse_normalized("foo=bar; Domain=.EXAMPLE.com").unwrap();
+        assert!(changed_flag);
+        assert_eq!(c, Cookie::parse("foo=bar; Domain=.EXAMPLE.com").unwrap());
+        assert!(changed("foo=bar; Domain=Example.com"));
+        assert!(changed("foo=bar; Domain=.example.com"));
+        assert!(changed("foo=bar; Domain=example.com:8080"));
+        assert!(changed("foo=bar; Max-Age=-1"));
+        assert!(changed("foo=bar; Max-Age=99999999999999999999"));
+        assert!(changed("foo=bar; Max-Age=abc"));
+        assert!(changed("foo=bar; Max-Age=5; Max-Age=6"));
+
+        assert!(!changed("foo=bar"));
+        assert!(!changed("foo=bar; Domain=example.com; Max-Age=60; Path=/"));
+        assert!(!changed("foo=bar; domain = example.com ; max-age=0"));
+        assert!(!changed("foo=bar; Domain=; Max-Age="));
+        assert!(!changed("foo=bar; Path=/A; Secure; HttpOnly"));
+        assert!(Cookie::parse_normalized("foo").is_err());
+    }
+
+    #[test]
+    fn two_digit_year_expires() {
+        let years = [("Thursday, 09-Jun-99 10:18:14 GMT", 1999),
+                     ("Wednesday, 09-Jun-21 10:18:14 GMT", 2021),
+                     ("Wed, 09-Jun-21 10:18:14 GMT", 2021),
+                     ("Wed, 09 Jun 69 10:18:14 GMT", 2069),
+                     ("Wed, 09 Jun 70 10:18:14 GMT", 1970),
+                     ("Wed, 09 Jun 2021 10:18:14 GMT", 2021),
+                     ("Wed, 09 Jun 1950 10:18:14 GMT", 1950)];
+        for &(date, year) in years.iter() {
+            let cookie = Cookie::parse(&format!("foo=bar; Expires={}", date)).unwrap();
+            assert_eq!(cookie.expires.map(|t| t.tm_year + 1900), Some(year), "{}", date);
+        }
+    }
+
+    #[test]
+    fn parse_request_header() {
+        let cookies = Cookie::parse_request_header(" a=1;b = 2 ;; c=x=y ; ").unwrap();
+        let pairs = cookies.iter()
+                           .map(|c| (&c.name[..], &c.value[..]))
+                           .collect::<Vec<_>>();
+        assert_eq!(pairs, [("a", "1"), ("b", "2"), ("c", "x=y")]);
+        assert!(cookies.iter().all(|c| c.custom.is_empty() && c.path.is_none()));
+
+        let cookies = Cookie::parse_request_header("a=1; Path=/; Secure=1").unwrap();
+        assert_eq!(cookies.len(), 3);
+        assert_eq!(cookies[1].name, "Path");
+        assert!(!cookies[0].secure);
+
+        assert!(Cookie::parse_request_header("").unwrap().is_empty());
+        assert!(Cookie::parse_request_header(" ; ;").unwrap().is_empty());
+        assert!(Cookie::parse_request_header("a=1; b").is_err());
+        assert!(Cookie::parse_request_header("a=1; =2").is_err());
+    }
+
+    #[test]
+    fn header_block() {
+        let block = "HTTP/1.1 200 OK\r\n\
+                     Set-Cookie: a=1; Path=/; HttpOnly\r\n\
+                     X-Set-Cookie: x=1\r\n\
+                     Content-Length: 0\r\n\
+                     SET-COOKIE:b=2\r\n\
+                     Set-Cookie: \r\n\
+                     \r\n";
+        let cookies = Cookie::parse_header_block(block);
+        assert_eq!(cookies.len(), 3);
+
+        let a = cookies[0].as_ref().unwrap();
+        assert_eq!(a.name, "a");
+        assert_eq!(a.value, "1");
+        assert_eq!(a.path, Some("/".to_string()));
+        assert!(a.httponly);
+        let b = cookies[1].as_ref().unwrap();
+        assert_eq!(b, &Cookie::new("b".to_string(), "2".to_string()));
+        assert!(cookies[2].is_err());
+
+        assert!(Cookie::parse_header_block("").is_empty());
+        assert!(Cookie::parse_header_block("Set-Cooki\u{e9}: a=1").is_empty());
+    }
+
+    #[test]
+    fn is_expired_at() {
+        use time::{self, Duration};
+
+        let now = time::now_utc();
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        assert!(!cookie.is_expired_at(now));
+
+        cookie.expires = Some(now - Duration::seconds(1));
+        assert!(cookie.is_expired_at(now));
+        cookie.expires = Some(now);
+        assert!(cookie.is_expired_at(now));
+        cookie.expires = Some(now + Duration::seconds(1));
+        assert!(!cookie.is_expired_at(now));
+
+        cookie.max_age = Some(0);
+        assert!(cookie.is_expired_at(now));
+        cookie.max_age = Some(60);
+        cookie.expires = Some(now - Duration::seconds(1));
+        assert!(!cookie.is_expired_at(now));
+    }
+
+    #[test]
+    fn quoted_whitespace() {
+        assert_eq!(Cookie::parse("foo= bar ").unwrap().value, "bar");
+        assert_eq!(Cookie::parse("foo=\t bar\t ; Secure").unwrap().value, "bar");
+        assert_eq!(Cookie::parse("foo=\" bar \"").unwrap().value, " bar ");
+        assert_eq!(Cookie::parse("foo= \" bar \" ; Secure").unwrap().value, " bar ");
+        assert_eq!(Cookie::parse("foo=\"a  b\"").unwrap().value, "a  b");
+    }
+
+    #[test]
+    fn double_quotes() {
+        fn value(s: &str) -> String {
+            Cookie::parse(s).unwrap().value
+        }
+
+        assert_eq!(value("foo=\"bar baz\""), "bar baz");
+        assert_eq!(value("foo=\"\""), "");
+        assert_eq!(value("foo=\"\"\"\""), "\"\"");
+        assert_eq!(value("foo=\""), "\"");
+        assert_eq!(value("foo=\"bar"), "\"bar");
+        assert_eq!(value("foo=bar\""), "bar\"");
+        assert_eq!(value("foo=b\"a\"r"), "b\"a\"r");
+        assert_eq!(Cookie::parse_borrowed("foo=\"bar\"").unwrap().value, "bar");
+
+        let c = Cookie::parse("foo=\"bar baz\"; Path=/").unwrap();
+        assert_eq!(c.to_string(), "foo=\"bar baz\"; Path=/");
+        assert_eq!(c.pair().to_string(), "foo=bar baz");
+        assert_eq!(c.header_parts(), ("foo=\"bar baz\"".to_string(), "Path=/".to_string()));
+        assert_eq!(c.pair_len(), 13);
+        assert_eq!(Cookie::parse("foo=\"bar\"").unwrap().to_string(), "foo=bar");
+
+        for v in &["a,b", " lead", "\"q\"", "\"", "\"open", "plain", ""] {
+            let c = Cookie::new("foo".to_string(), v.to_string());
+            assert_eq!(Cookie::parse(&c.to_string()).unwrap(), c, "{:?}", v);
+        }
+    }
+
+    #[test]
+    fn effective_expiry() {
+        use std::u64;
+        use time::{self, Duration};
+
+        let now = time::now_utc();
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        assert_eq!(cookie.effective_expiry(now), None);
+
+        let expires = now + Duration::days(1);
+        cookie.expires = Some(expires);
+        assert_eq!(cookie.effective_expiry(now), Some(expires));
+
+        cookie.max_age = Some(60);
+        assert_eq!(cookie.effective_expiry(now), Some(now + Duration::seconds(60)));
+        cookie.max_age = Some(0);
+        assert_eq!(cookie.effective_expiry(now), Some(now));
+
+        cookie.max_age = Some(u64::MAX);
+        let expiry = cookie.effective_expiry(now).unwrap();
+        assert_eq!(expiry.tm_year + 1900, 9999);
+        assert_eq!(expiry.to_timespec().sec, 253402300799);
+    }
+
+    #[test]
+    fn is_expired() {
+        use time::{self, Duration};
+
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        assert!(!cookie.is_expired());
+        assert_eq!(cookie.time_remaining(), None);
+
+        cookie.expires = Some(time::now_utc() - Duration::minutes(1));
+        assert!(cookie.is_expired());
+        assert_eq!(cookie.time_remaining(), Some(Duration::zero()));
+
+        cookie.expires = Some(time::now_utc() + Duration::hours(1));
+        assert!(!cookie.is_expired());
+        let remaining = cookie.time_remaining().unwrap();
+        assert!(remaining > Duration::minutes(59) && remaining <= Duration::hours(1));
+
+        cookie.max_age = Some(0);
+        assert!(cookie.is_expired());
+        cookie.max_age = Some(60);
+        cookie.expires = Some(time::now_utc() - Duration::minutes(1));
+        assert!(!cookie.is_expired());
+        assert_eq!(cookie.time_remaining(), Some(Duration::seconds(60)));
+    }
+
+    #[test]
+    fn try_set_max_age() {
+        use std::{i64, u64};
+        use super::Error;
+
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        cookie.try_set_max_age(0).unwrap();
+        assert_eq!(cookie.max_age, Some(0));
+        cookie.try_set_max_age(86400).unwrap();
+        assert_eq!(cookie.max_age, Some(86400));
+        cookie.try_set_max_age(253402300799).unwrap();
+
+        assert_eq!(cookie.try_set_max_age(u64::MAX).unwrap_err(),
+                   Error::InvalidMaxAge(u64::MAX.to_string()));
+        assert!(cookie.try_set_max_age(i64::MAX as u64).is_err());
+        assert!(cookie.try_set_max_age(253402300800).is_err());
+        assert_eq!(cookie.max_age, Some(253402300799));
+    }
+
+    #[test]
+    fn set_raw_expires() {
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        cookie.set_raw_expires("Wed, 09 Jun 2021 10:18:14 GMT").unwrap();
+        let expected = Cookie::parse("foo=bar; Expires=Wed, 09 Jun 2021 10:18:14 GMT")
+            .unwrap();
+        assert_eq!(cookie.expires, expected.expires);
+
+        assert!(cookie.set_raw_expires("Wed, 32 Jun 2021 10:18:14 GMT").is_err());
+        assert!(cookie.set_raw_expires("").is_err());
+        assert_eq!(cookie.expires, expected.expires);
+    }
+
+    #[test]
+    fn time_until_expiry() {
+        use time::{self, Duration};
+
+        let now = time::now_utc();
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        assert_eq!(cookie.time_until_expiry(now), None);
+
+        cookie.expires = Some(now + Duration::minutes(5));
+        assert_eq!(cookie.time_until_expiry(now), Some(Duration::minutes(5)));
+        cookie.expires = Some(now - Duration::minutes(5));
+        assert_eq!(cookie.time_until_expiry(now), Some(Duration::zero()));
+
+        cookie.max_age = Some(90);
+        assert_eq!(cookie.time_until_expiry(now), Some(Duration::seconds(90)));
+        cookie.max_age = Some(0);
+        assert_eq!(cookie.time_until_expiry(now), Some(Duration::zero()));
+    }
+
+    #[test]
+    fn sent_on_cross_site() {
+        use super::SameSite;
+
+        // (same_site, [safe nav, unsafe nav, safe subresource, unsafe subresource])
+        let table = [(Some(SameSite::Strict), [false, false, false, false]),
+                     (Some(SameSite::Lax), [true, false, false, false]),
+                     (Some(SameSite::None), [true, true, true, true]),
+                     (None, [true, true, false, false])];
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        for &(same_site, expected) in table.iter() {
+            cookie.same_site = same_site;
+            let actual = [cookie.sent_on_cross_site(true, true),
+                          cookie.sent_on_cross_site(false, true),
+                          cookie.sent_on_cross_site(true, false),
+                          cookie.sent_on_cross_site(false, false)];
+            assert_eq!(actual, expected, "{:?}", same_site);
+        }
+    }
+
+    #[test]
+    fn parse_bytes_lossy() {
+        let cookie = Cookie::parse_bytes_lossy(b"foo=a\xffb; Path=/").unwrap();
+        assert_eq!(cookie.name, "foo");
+        assert_eq!(cookie.value, "a\u{fffd}b");
+        assert_eq!(cookie.path, Some("/".to_string()));
+
+        let cookie = Cookie::parse_bytes_lossy("foo=bär".as_bytes()).unwrap();
+        assert_eq!(cookie.value, "bär");
+        assert!(Cookie::parse_bytes_lossy(b"\xff").is_err());
+    }
+
+    #[test]
+    fn control_characters() {
+        assert!(Cookie::parse("foo\nbar=baz").is_err());
+        assert!(Cookie::parse("foo\rbar=baz").is_err());
+        assert!(Cookie::parse("foo\0bar=baz").is_err());
+        assert!(Cookie::parse("foo=ba\nz").is_err());
+        assert!(Cookie::parse("foo=ba\r\nSet-Cookie: evil=1").is_err());
+        assert!(Cookie::parse("foo=b\x7fz").is_err());
+        assert!(Cookie::parse("foo=baz\n").is_ok());
+    }
+
+    #[test]
+    fn domain_port() {
+        fn domain(header: &str) -> Option<String> {
+            Cookie::parse(header).unwrap().domain
+        }
+
+        assert_eq!(domain("foo=bar; Domain=example.com:8080"),
+                   Some("example.com".to_string()));
+        assert_eq!(domain("foo=bar; Domain=.EXAMPLE.com:443"),
+                   Some("example.com".to_string()));
+        assert_eq!(domain("foo=bar; Domain=127.0.0.1:80"),
+                   Some("127.0.0.1".to_string()));
+        assert_eq!(domain("foo=bar; Domain=[::1]:80"), Some("[::1]".to_string()));
+        assert_eq!(domain("foo=bar; Domain=::1"), Some("::1".to_string()));
+        assert_eq!(domain("foo=bar; Domain=example.com:"),
+                   Some("example.com:".to_string()));
+        assert_eq!(domain("foo=bar; Domain=example.com:http"),
+                   Some("example.com:http".to_string()));
+    }
+
+    #[test]
+    fn error_variants() {
+        use super::Error;
+        use std::error::Error as StdError;
+
+        assert_eq!(Cookie::parse("foo").unwrap_err(), Error::MissingPair);
+        assert_eq!(Cookie::parse("=bar").unwrap_err(), Error::EmptyName);
+        assert_eq!(Cookie::parse("foo=b\0r").unwrap_err(), Error::ControlCharacter);
+        assert_eq!(Cookie::parse_encoded("foo=%FF").unwrap_err(), Error::InvalidEncoding);
+
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        assert_eq!(cookie.set_raw_expires("soon").unwrap_err(),
+                   Error::InvalidDate("soon".to_string()));
+        assert_eq!(cookie.try_scope_to_path("admin").unwrap_err(),
+                   Error::InvalidPath("admin".to_string()));
+        assert_eq!(cookie.to_string_checked_within(4).unwrap_err(), Error::TooLarge);
+
+        let err = Cookie::parse("foo").unwrap_err();
+        assert_eq!(err.to_string(), "cookie has no name=value pair");
+        assert_eq!(err.description(), "cookie has no name=value pair");
+        assert_eq!(Error::InvalidDate("soon".to_string()).to_string(),
+                   "invalid cookie date: soon");
+        match Cookie::parse("") {
+            Err(_) => {}
+            Ok(_) => panic!("parsed an empty header"),
+        }
+    }
+
+    #[test]
+    fn attributes_before_pair() {
+        assert!(Cookie::parse("Secure; foo=bar").is_err());
+        assert!(Cookie::parse("HttpOnly;foo=bar; Path=/").is_err());
+        assert!(Cookie::parse("; foo=bar").is_err());
+
+        let cookie = Cookie::parse("Path=/; foo=bar").unwrap();
+        assert_eq!(cookie.name, "Path");
+        assert_eq!(cookie.value, "/");
+        assert_eq!(cookie.custom.get("foo"), Some(&"bar".to_string()));
+    }
+
+    #[test]
+    fn pair_then_attributes() {
+        let cookie = Cookie::parse("foo=a=b; Secure").unwrap();
+        assert_eq!(cookie.name, "foo");
+        assert_eq!(cookie.value, "a=b");
+        assert!(cookie.secure);
+
+        let cookie = Cookie::parse("foo=a; Path=/").unwrap();
+        assert_eq!(cookie.value, "a");
+        assert_eq!(cookie.path, Some("/".to_string()));
+
+        let cookie = Cookie::parse("foo==; x=y=z").unwrap();
+        assert_eq!(cookie.value, "=");
+        assert_eq!(cookie.custom.get("x"), Some(&"y=z".to_string()));
+
+        let cookie = Cookie::parse("foo=a;b=c").unwrap();
+        assert_eq!(cookie.value, "a");
+        assert_eq!(cookie.custom.get("b"), Some(&"c".to_string()));
+    }
+
+    #[test]
+    fn all_variants() {
+        use super::{Priority, SameSite};
+
+        assert_eq!(SameSite::all(), [SameSite::Strict, SameSite::Lax, SameSite::None]);
+        assert_eq!(Priority::all(), [Priority::Low, Priority::Medium, Priority::High]);
+        let names = SameSite::all().iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(names, ["Strict", "Lax", "None"]);
+    }
+
+    #[test]
+    fn same_site_and_priority() {
+        use super::{Priority, SameSite};
+
+        for header in &["foo=bar; samesite=LAX", "foo=bar; SameSite=lax",
+                        "foo=bar; SAMESITE = Lax"] {
+            let cookie = Cookie::parse(header).unwrap();
+            assert_eq!(cookie.same_site, Some(SameSite::Lax));
+            assert!(cookie.custom.is_empty());
+            assert_eq!(cookie.to_string(), "foo=bar; SameSite=Lax");
+        }
+        let cookie = Cookie::parse("foo=bar; SameSite=STRICT").unwrap();
+        assert_eq!(cookie.to_string(), "foo=bar; SameSite=Strict");
+        let cookie = Cookie::parse("foo=bar; samesite=none; secure").unwrap();
+        assert_eq!(cookie.to_string(), "foo=bar; Secure; SameSite=None");
+
+        for header in &["foo=bar; SameSite=Bogus", "foo=bar; SameSite",
+                        "foo=bar; SameSite=Lax; samesite=bogus"] {
+            let cookie = Cookie::parse(header).unwrap();
+            assert_eq!(cookie.same_site, None);
+            assert!(cookie.custom.is_empty());
+            assert_eq!(cookie.to_string(), "foo=bar");
+        }
+
+        for header in &["foo=bar; priority=HIGH", "foo=bar; Priority=high"] {
+            let cookie = Cookie::parse(header).unwrap();
+            assert_eq!(cookie.priority, Some(Priority::High));
+            assert_eq!(cookie.to_string(), "foo=bar; Priority=High");
+        }
+        let cookie = Cookie::parse("foo=bar; PRIORITY=low; Priority=Medium").unwrap();
+        assert_eq!(cookie.to_string(), "foo=bar; Priority=Medium");
+
+        for header in &["foo=bar; Priority=bogus", "foo=bar; Priority",
+                        "foo=bar; Priority=High; priority=bogus"] {
+            let cookie = Cookie::parse(header).unwrap();
+            assert_eq!(cookie.priority, None);
+            assert!(cookie.custom.is_empty());
+            assert_eq!(cookie.to_string_checked(), Ok("foo=bar".to_string()));
+        }
+
+        let cookie = Cookie::parse("foo=bar; SameSite=Lax; Path=/; Priority=Low").unwrap();
+        let reparsed = Cookie::parse(&cookie.to_string()).unwrap();
+        assert_eq!(cookie, reparsed);
+        assert_eq!(reparsed.to_string(), "foo=bar; SameSite=Lax; Path=/; Priority=Low");
+    }
+
+    #[test]
+    fn same_site_none_explicit() {
+        use super::SameSite;
+
+        let c = Cookie::parse("foo=bar; SameSite=None").unwrap();
+        assert_eq!(c.same_site, Some(SameSite::None));
+        assert_eq!(Cookie::parse(&c.to_string()).unwrap().same_site, Some(SameSite::None));
+        assert_eq!(Cookie::parse_borrowed("foo=bar; SameSite=None").unwrap().same_site,
+                   Some(SameSite::None));
+
+        let c = Cookie::parse("foo=bar").unwrap();
+        assert_eq!(c.same_site, None);
+        assert_eq!(c.to_string(), "foo=bar");
+        assert_eq!(Cookie::parse_borrowed("foo=bar").unwrap().same_site, None);
+    }
+
+    #[test]
+    fn odd_characters() {
+        let expected = Cookie::new("foo".to_string(), "b%2Fr".to_string());
+        assert_eq!(Cookie::parse("foo=b%2Fr").ok().unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_detailed() {
+        use super::{Error, ParseError, ParseErrorKind};
+
+        fn check(s: &str, kind: ParseErrorKind, offset: usize) {
+            let err = Cookie::parse_detailed(s).unwrap_err();
+            assert_eq!(err, ParseError { kind: kind, offset: offset }, "{:?}", s);
+            assert_eq!(Error::from(err), Cookie::parse(s).unwrap_err());
+        }
+
+        check("foo", ParseErrorKind::MissingEquals, 3);
+        check("  foo; a=b", ParseErrorKind::MissingEquals, 5);
+        check("", ParseErrorKind::MissingEquals, 0);
+        check("=bar", ParseErrorKind::EmptyName, 0);
+        check("\u{feff} =bar", ParseErrorKind::EmptyName, 4);
+        check("f\x01oo=bar", ParseErrorKind::ControlCharInName, 1);
+        check("  foo\x7f =bar", ParseErrorKind::ControlCharInName, 5);
+        check("foo=bar\r\nX: y", ParseErrorKind::ControlCharInValue, 7);
+        check(" foo =  \x00bar; Path=/", ParseErrorKind::ControlCharInValue, 8);
+
+        let err = Cookie::parse_detailed("=bar").unwrap_err();
+        assert_eq!(err.to_string(), "cookie name is empty at byte 0");
+        assert_eq!(Cookie::parse_detailed("foo=bar; Secure").unwrap(),
+                   Cookie::parse("foo=bar; Secure").unwrap());
+    }
+
+    #[test]
+    fn parse_strict() {
+        use super::Error;
+
+        assert_eq!(Cookie::parse_strict(" foo = bar; Path=/").unwrap(),
+                   Cookie::parse("foo=bar; Path=/").unwrap());
+        assert_eq!(Cookie::parse_strict("fo o=bar"), Err(Error::InvalidName("fo o".to_string())));
+        for name in &["a@b", "a/b", "a(b)", "a:b", "a,b", "[a]", "a?", "{a}", "a\\b",
+                      "a\"b", "caf\u{e9}"] {
+            let header = format!("{}=bar", name);
+            assert!(Cookie::parse(&header).is_ok(), "{}", header);
+            assert_eq!(Cookie::parse_strict(&header), Err(Error::InvalidName(name.to_string())));
+        }
+        assert!(Cookie::parse_strict("fo;o=bar").is_err());
+        assert!(Cookie::parse_strict(";foo=bar").is_err());
+        assert!(Cookie::parse_strict("=bar").is_err());
+        assert!(Cookie::parse_strict("__Host-id.v2_x=1").is_ok());
+    }
+
+    #[test]
+    fn parse_verbose() {
+        use super::ParseWarning;
+
+        let header = "foo=bar; Path=/; Expires=dimanche, 23-novembre-2014 20:00:00 GMT; Secure";
+        let (c, warnings) = Cookie::parse_verbose(header).unwrap();
+        assert_eq!(c, Cookie::parse(header).unwrap());
+        assert_eq!(c.path, Some("/".to_string()));
+        assert!(c.secure);
+        assert!(c.expires.is_none());
+        assert_eq!(warnings, [ParseWarning::LocalizedExpires(
+            "dimanche, 23-novembre-2014 20:00:00 GMT".to_string())]);
+
+        let (c, warnings) = Cookie::parse_verbose("foo=bar; expires=Sun, 99 Nov 2014").unwrap();
+        assert!(c.expires.is_none());
+        assert_eq!(warnings, [ParseWarning::InvalidExpires("Sun, 99 Nov 2014".to_string())]);
+        assert_eq!(warnings[0].to_string(), "ignored invalid Expires date: Sun, 99 Nov 2014");
+
+        let (c, warnings) = Cookie::parse_verbose("foo=bar; Expires=Sun, 23 Nov 2014 \
+                                                   20:00:00 GMT; Expires=").unwrap();
+        assert!(c.expires.is_some());
+        assert!(warnings.is_empty());
+        assert!(Cookie::parse_verbose("foo").is_err());
+    }
+
+    #[test]
+    fn lenient_single_quotes() {
+        let c = Cookie::parse_lenient("foo='bar baz'; Secure").unwrap();
+        assert_eq!(c.value, "bar baz");
+        assert!(c.secure);
+        assert_eq!(Cookie::parse("foo='bar baz'").unwrap().value, "'bar baz'");
+
+        assert_eq!(Cookie::parse_lenient("foo=''").unwrap().value, "");
+        assert_eq!(Cookie::parse_lenient("foo='").unwrap().value, "'");
+        assert_eq!(Cookie::parse_lenient("foo='bar").unwrap().value, "'bar");
+        assert_eq!(Cookie::parse_lenient("foo=\"'bar'\"").unwrap().value, "bar");
+        assert!(Cookie::parse_lenient("foo").is_err());
+    }
+
+    #[test]
+    fn lenient_limited() {
+        let header = "foo=bar; a=1; b=2; c=3; d=4; Secure; HttpOnly";
+        let c = Cookie::parse_lenient_limited(header, 2).unwrap();
+        assert_eq!(c.custom.len(), 2);
+        assert_eq!(c.custom.get("a"), Some(&"1".to_string()));
+        assert_eq!(c.custom.get("b"), Some(&"2".to_string()));
+        assert!(!c.secure);
+        assert!(!c.httponly);
+
+        let c = Cookie::parse_lenient_limited(header, 0).unwrap();
+        assert_eq!(c, Cookie::new("foo".to_string(), "bar".to_string()));
+
+        let c = Cookie::parse_lenient_limited(header, 100).unwrap();
+        assert_eq!(c, Cookie::parse(header).unwrap());
+        assert!(Cookie::parse_lenient_limited("bar", 2).is_err());
+    }
+
+    #[test]
+    fn to_string_checked() {
+        use std::i32;
+        use time;
+
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        assert_eq!(cookie.to_string_checked().unwrap(), "foo=bar");
+
+        let expires = time::strptime("Sun, 23 Nov 2014 20:00:00 UTC",
+                                     "%a, %d %b %Y %H:%M:%S %Z").unwrap();
+        cookie.expires = Some(expires);
+        assert_eq!(cookie.to_string_checked().unwrap(),
+                   "foo=bar; Expires=Sun, 23 Nov 2014 20:00:00 GMT");
+
+        let mut bad = expires;
+        bad.tm_mon = 12;
+        cookie.expires = Some(bad);
+        assert!(cookie.to_string_checked().is_err());
+
+        let mut bad = expires;
+        bad.tm_wday = -1;
+        cookie.expires = Some(bad);
+        assert!(cookie.to_string_checked().is_err());
+
+        let mut bad = expires;
+        bad.tm_year = 20000;
+        cookie.expires = Some(bad);
+        assert!(cookie.to_string_checked().is_err());
+
+        for &year in &[i32::MAX, i32::MIN] {
+            let mut bad = expires;
+            bad.tm_year = year;
+            cookie.expires = Some(bad);
+            assert!(cookie.to_string_checked().is_err());
+            assert!(cookie.validate().is_err());
         }
-        match self.domain {
-            Some(ref s) => try!(write!(f, "; Domain={}", s)),
-            None => {}
+
+        cookie.expires = None;
+        cookie.name = String::new();
+        assert!(cookie.to_string_checked().is_err());
+    }
+
+    #[test]
+    fn same_key() {
+        let mut a = Cookie::new("foo".to_string(), "bar".to_string());
+        let mut b = Cookie::new("foo".to_string(), "baz".to_string());
+        assert!(a.same_key(&b));
+
+        a.domain = Some("example.com".to_string());
+        assert!(!a.same_key(&b));
+        b.domain = Some(".Example.COM".to_string());
+        assert!(a.same_key(&b));
+
+        a.path = Some("/".to_string());
+        b.path = Some("/foo".to_string());
+        assert!(!a.same_key(&b));
+
+        b.path = Some("/".to_string());
+        b.name = "bar".to_string();
+        assert!(!a.same_key(&b));
+    }
+
+    #[test]
+    fn could_shadow() {
+        let mut a = Cookie::new("foo".to_string(), "bar".to_string());
+        let mut b = Cookie::new("foo".to_string(), "baz".to_string());
+        assert!(!a.could_shadow(&b));
+
+        a.path = Some("/".to_string());
+        b.path = Some("/foo".to_string());
+        assert!(a.could_shadow(&b));
+        assert!(b.could_shadow(&a));
+
+        b.path = Some("/".to_string());
+        b.domain = Some("example.com".to_string());
+        assert!(a.could_shadow(&b));
+
+        b.name = "other".to_string();
+        assert!(!a.could_shadow(&b));
+    }
+
+    #[test]
+    fn matches_domain() {
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        assert!(cookie.is_host_only());
+        assert!(!cookie.matches_domain("example.com"));
+        assert!(!cookie.matches_domain("www.example.com"));
+
+        cookie.domain = Some("example.com".to_string());
+        assert!(!cookie.is_host_only());
+        assert!(cookie.matches_domain("example.com"));
+        assert!(cookie.matches_domain("www.example.com"));
+        assert!(!cookie.matches_domain("wwwexample.com"));
+        assert!(!cookie.matches_domain("example.org"));
+
+        cookie.domain = Some(".Example.COM".to_string());
+        assert!(cookie.matches_domain("EXAMPLE.com"));
+        assert!(cookie.matches_domain("a.b.example.com"));
+
+        cookie.domain = Some("127.0.0.1".to_string());
+        assert!(cookie.matches_domain("127.0.0.1"));
+        assert!(!cookie.matches_domain("x.127.0.0.1"));
+
+        cookie.make_host_only();
+        assert!(cookie.is_host_only());
+        assert!(!cookie.matches_domain("www.example.com"));
+    }
+
+    #[test]
+    fn matches_path() {
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        assert!(cookie.matches_path("/"));
+        assert!(cookie.matches_path("/anything"));
+
+        cookie.path = Some("/foo".to_string());
+        assert!(cookie.matches_path("/foo"));
+        assert!(cookie.matches_path("/foo/"));
+        assert!(cookie.matches_path("/foo/bar"));
+        assert!(!cookie.matches_path("/foobar"));
+        assert!(!cookie.matches_path("/"));
+        assert!(!cookie.matches_path("/bar/foo"));
+
+        cookie.path = Some("/foo/".to_string());
+        assert!(cookie.matches_path("/foo/bar"));
+        assert!(!cookie.matches_path("/foo"));
+    }
+
+    #[test]
+    fn matches() {
+        use url::Url;
+
+        let url = |s: &str| Url::parse(s).unwrap();
+        let mut cookie = Cookie::parse("foo=bar; Domain=example.com; Path=/app").unwrap();
+        assert!(cookie.matches(&url("http://example.com/app")));
+        assert!(cookie.matches(&url("https://www.example.com/app/page")));
+        assert!(!cookie.matches(&url("https://example.org/app")));
+        assert!(!cookie.matches(&url("https://example.com/other")));
+
+        cookie.secure = true;
+        assert!(cookie.matches(&url("https://example.com/app")));
+        assert!(!cookie.matches(&url("http://example.com/app")));
+
+        let cookie = Cookie::parse("foo=bar; Domain=localhost; Secure").unwrap();
+        assert!(cookie.matches(&url("http://localhost/")));
+        assert!(cookie.matches(&url("http://app.localhost:8080/")));
+        let cookie = Cookie::parse("foo=bar; Domain=127.0.0.1; Secure").unwrap();
+        assert!(cookie.matches(&url("http://127.0.0.1:3000/")));
+        let cookie = Cookie::parse("foo=bar; Domain=[::1]; Secure").unwrap();
+        assert!(cookie.matches(&url("http://[::1]/")));
+        let cookie = Cookie::parse("foo=bar; Domain=example.com; Secure").unwrap();
+        assert!(!cookie.matches(&url("http://example.com/")));
+
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        assert!(cookie.matches(&url("http://example.com/")));
+        assert!(cookie.matches(&url("http://localhost/any/path")));
+        cookie.secure = true;
+        assert!(cookie.matches(&url("http://localhost/")));
+        assert!(cookie.matches(&url("http://127.0.0.1:8000/")));
+        assert!(cookie.matches(&url("https://example.com/")));
+        assert!(!cookie.matches(&url("http://example.com/")));
+        cookie.path = Some("/app".to_string());
+        assert!(!cookie.matches(&url("http://localhost/other")));
+    }
+
+    #[test]
+    fn domain_valid_for() {
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        assert!(cookie.domain_valid_for("example.org"));
+
+        cookie.domain = Some("example.com".to_string());
+        assert!(cookie.domain_valid_for("example.com"));
+        assert!(cookie.domain_valid_for("www.example.com"));
+        assert!(cookie.domain_valid_for("WWW.Example.COM"));
+        assert!(!cookie.domain_valid_for("example.org"));
+        assert!(!cookie.domain_valid_for("notexample.com"));
+        assert!(!cookie.domain_valid_for("com"));
+
+        cookie.domain = Some(".Example.com".to_string());
+        assert!(cookie.domain_valid_for("a.b.example.com"));
+
+        cookie.domain = Some("0.0.1".to_string());
+        assert!(!cookie.domain_valid_for("127.0.0.1"));
+        cookie.domain = Some("127.0.0.1".to_string());
+        assert!(cookie.domain_valid_for("127.0.0.1"));
+    }
+
+    #[test]
+    fn encoded() {
+        let lower = Cookie::parse_encoded("foo=a%2fb%3b").unwrap();
+        let upper = Cookie::parse_encoded("foo=a%2Fb%3B").unwrap();
+        assert_eq!(lower.value, "a/b;");
+        assert_eq!(lower, upper);
+
+        let mut cookie = Cookie::new("f;o".to_string(), "a\"b,c%\u{e9}".to_string());
+        cookie.path = Some("/".to_string());
+        let encoded = cookie.encoded().to_string();
+        assert_eq!(encoded, "f%3Bo=a%22b%2Cc%25%C3%A9; Path=/");
+        assert_eq!(Cookie::parse_encoded(&encoded).unwrap(), cookie);
+
+        assert!(Cookie::parse_encoded("foo=%FF").is_err());
+
+        let cookie = Cookie::new("foo".to_string(), "bar baz;".to_string());
+        let encoded = cookie.encoded().to_string();
+        assert_eq!(encoded, "foo=bar%20baz%3B");
+        assert_eq!(Cookie::parse(&encoded).unwrap().decode_value(), b"bar baz;");
+        assert_eq!(Cookie::parse("foo=%FF%00a").unwrap().decode_value(), b"\xff\0a");
+        assert_eq!(Cookie::parse("foo=100%").unwrap().decode_value(), b"100%");
+    }
+
+    #[test]
+    fn valid_names() {
+        use super::is_valid_name;
+
+        assert!(is_valid_name("foo"));
+        assert!(is_valid_name("__Host-foo"));
+        assert!(is_valid_name("a!#$%&'*+.^_`|~9"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("fo o"));
+        assert!(!is_valid_name("foo\t"));
+        assert!(!is_valid_name("foo\n"));
+        assert!(!is_valid_name("f\u{e9}"));
+        for sep in "()<>@,;:\\\"/[]?={}".chars() {
+            assert!(!is_valid_name(&format!("foo{}", sep)));
         }
-        match self.max_age {
-            Some(n) => try!(write!(f, "; Max-Age={}", n)),
-            None => {}
+    }
+
+    #[test]
+    fn valid_values() {
+        use super::is_valid_value;
+
+        assert!(is_valid_value(""));
+        assert!(is_valid_value("bar"));
+        assert!(is_valid_value("a=b/c:d[e]"));
+        assert!(is_valid_value("\"bar\""));
+        assert!(is_valid_value("\"\""));
+        assert!(!is_valid_value("\""));
+        assert!(!is_valid_value("\"bar"));
+        assert!(!is_valid_value("\"b\"r\""));
+        assert!(!is_valid_value("bar baz"));
+        assert!(!is_valid_value("a,b"));
+        assert!(!is_valid_value("a;b"));
+        assert!(!is_valid_value("a\\b"));
+        assert!(!is_valid_value("a\x7fb"));
+        assert!(!is_valid_value("\u{e9}"));
+    }
+
+    #[test]
+    fn attribute_order() {
+        use super::AttributeKind;
+
+        let mut cookie = Cookie::parse("foo=bar; Secure; HttpOnly; Path=/; \
+                                        SameSite=None; x=y").unwrap();
+        assert_eq!(cookie.to_header_string_with_order(AttributeKind::default_order()),
+                   cookie.to_string());
+
+        let order = [AttributeKind::SameSite, AttributeKind::Secure];
+        assert_eq!(cookie.to_header_string_with_order(&order),
+                   "foo=bar; SameSite=None; Secure; HttpOnly; Path=/; x=y");
+
+        let order = [AttributeKind::Custom, AttributeKind::Custom, AttributeKind::HttpOnly];
+        assert_eq!(cookie.to_header_string_with_order(&order),
+                   "foo=bar; x=y; HttpOnly; Secure; SameSite=None; Path=/");
+
+        cookie.max_age = Some(10);
+        let order = [AttributeKind::MaxAge];
+        assert_eq!(cookie.to_header_string_with_order(&order),
+                   "foo=bar; Max-Age=10; HttpOnly; Secure; SameSite=None; Path=/; x=y");
+    }
+
+    #[test]
+    fn header_parts() {
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        assert_eq!(cookie.header_parts(), ("foo=bar".to_string(), String::new()));
+
+        cookie.httponly = true;
+        cookie.path = Some("/foo".to_string());
+        cookie.max_age = Some(4);
+        cookie.custom.insert("wut".to_string(), "lol".to_string());
+        let (pair, attrs) = cookie.header_parts();
+        assert_eq!(pair, "foo=bar");
+        assert_eq!(attrs, "HttpOnly; Path=/foo; Max-Age=4; wut=lol");
+        assert_eq!(format!("{}; {}", pair, attrs), cookie.to_string());
+    }
+
+    #[test]
+    fn to_set_cookie2() {
+        let cookie = Cookie::parse("Part_Number=Rocket_Launcher_0001; Path=/acme; \
+                                    Domain=Example.com; Max-Age=3600; Secure; \
+                                    HttpOnly; wut=lol").unwrap();
+        assert_eq!(cookie.to_set_cookie2(),
+                   "Part_Number=\"Rocket_Launcher_0001\"; Version=\"1\"; \
+                    Path=\"/acme\"; Domain=\".example.com\"; Max-Age=\"3600\"; Secure");
+
+        let cookie = Cookie::new("foo".to_string(), "say \"hi\\".to_string());
+        assert_eq!(cookie.to_set_cookie2(),
+                   "foo=\"say \\\"hi\\\\\"; Version=\"1\"; Discard");
+    }
+
+    #[test]
+    fn to_h2_header() {
+        let cookie = Cookie::parse("foo=bar; HttpOnly; Path=/").unwrap();
+        let (name, value) = cookie.to_h2_header();
+        assert_eq!(name, "set-cookie");
+        assert_eq!(name, name.to_lowercase());
+        assert_eq!(value, "foo=bar; HttpOnly; Path=/");
+    }
+
+    #[test]
+    fn parse_iter() {
+        use std::cell::Cell;
+
+        let parsed = Cell::new(0);
+        let headers = vec!["a=1; Path=/", "b=2; Secure", "c"];
+        let mut iter = super::parse_iter(headers.iter().map(|h| {
+            parsed.set(parsed.get() + 1);
+            h.to_string()
+        }));
+        assert_eq!(parsed.get(), 0);
+
+        let a = iter.next().unwrap().unwrap();
+        assert_eq!(parsed.get(), 1);
+        assert_eq!(a.name, "a");
+        assert_eq!(a.path, Some("/".to_string()));
+        let b = iter.next().unwrap().unwrap();
+        assert_eq!(b.name, "b");
+        assert!(b.secure);
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+        assert_eq!(parsed.get(), 3);
+    }
+
+    #[test]
+    fn validate() {
+        use super::Error;
+
+        let mut c = Cookie::parse("foo=bar; Path=/; x=1; Flag").unwrap();
+        assert_eq!(c.validate(), Ok(()));
+
+        c.custom.insert("path".to_string(), "/other".to_string());
+        assert_eq!(c.validate(), Err(Error::ReservedAttribute("path".to_string())));
+        assert_eq!(c.to_string_checked(), Err(Error::ReservedAttribute("path".to_string())));
+        c.custom.remove("path");
+
+        c.flag_attrs.push("SECURE".to_string());
+        assert_eq!(c.validate(), Err(Error::ReservedAttribute("SECURE".to_string())));
+        c.flag_attrs.pop();
+
+        for key in &["Secure", "HttpOnly", "Max-Age", "DOMAIN", "samesite", "Priority",
+                     "Expires"] {
+            assert_eq!(c.try_set_custom(*key, "1"),
+                       Err(Error::ReservedAttribute(key.to_string())));
         }
-        match self.expires {
-            Some(ref t) => try!(write!(f, "; Expires={}", t.rfc822())),
-            None => {}
+        assert_eq!(c.custom.len(), 1);
+        assert_eq!(c.try_set_custom("x", "2"), Ok(Some("1".to_string())));
+        assert_eq!(c.try_set_custom("Max-Age-Hint", "2"), Ok(None));
+        assert!(c.validate().is_ok());
+
+        assert_eq!(Cookie::new(String::new(), "1".to_string()).validate(), Err(Error::EmptyName));
+    }
+
+    #[test]
+    fn flags_string() {
+        use super::SameSite;
+
+        let c = Cookie::parse("foo=bar; Secure; HttpOnly; SameSite=Lax").unwrap();
+        assert_eq!(c.flags_string(), "SH SS:Lax");
+
+        let mut c = Cookie::parse("foo=bar; Path=/; Max-Age=1").unwrap();
+        assert_eq!(c.flags_string(), "");
+        c.httponly = true;
+        assert_eq!(c.flags_string(), "H");
+        c.secure = true;
+        assert_eq!(c.flags_string(), "SH");
+        c.httponly = false;
+        c.same_site = Some(SameSite::Strict);
+        assert_eq!(c.flags_string(), "S SS:Strict");
+        c.secure = false;
+        c.same_site = Some(SameSite::None);
+        assert_eq!(c.flags_string(), "SS:None");
+    }
+
+    #[test]
+    fn to_map() {
+        let c = Cookie::parse("foo=bar; Path=/app; Domain=example.com; Max-Age=60; \
+                               Expires=Sun, 23 Nov 2014 20:00:00 GMT; HttpOnly; \
+                               SameSite=Lax; Priority=High; x=1").unwrap();
+        let map = c.to_map();
+        let mut keys = map.keys().map(|k| &k[..]).collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, ["domain", "expires", "http_only", "max_age", "name", "path",
+                          "priority", "same_site", "secure", "value"]);
+        assert_eq!(map["name"], "foo");
+        assert_eq!(map["value"], "bar");
+        assert_eq!(map["path"], "/app");
+        assert_eq!(map["domain"], "example.com");
+        assert_eq!(map["max_age"], "60");
+        assert_eq!(map["expires"], "Sun, 23 Nov 2014 20:00:00 GMT");
+        assert_eq!(map["http_only"], "true");
+        assert_eq!(map["secure"], "false");
+        assert_eq!(map["same_site"], "Lax");
+        assert_eq!(map["priority"], "High");
+
+        let map = Cookie::new("a".to_string(), "".to_string()).to_map();
+        assert_eq!(map.len(), 4);
+        assert_eq!(map["value"], "");
+    }
+
+    #[test]
+    fn from_tuple() {
+        let c: Cookie = ("session", "abc").into();
+        assert_eq!(c, Cookie::new("session".to_string(), "abc".to_string()));
+
+        let c = Cookie::from(("session".to_string(), String::from("abc")));
+        assert_eq!(c, Cookie::new("session".to_string(), "abc".to_string()));
+
+        let pairs = vec![("a", "1"), ("b", "2")];
+        let cookies = pairs.into_iter().map(Cookie::from).collect::<Vec<_>>();
+        assert_eq!(cookies[1].to_string(), "b=2");
+    }
+
+    #[test]
+    fn default() {
+        assert_eq!(Cookie::default(), Cookie::new(String::new(), String::new()));
+
+        let c = Cookie {
+            name: "x".into(),
+            value: "1".into(),
+            path: Some("/".into()),
+            ..Default::default()
+        };
+        assert_eq!(c, Cookie::parse("x=1; Path=/").unwrap());
+    }
+
+    #[test]
+    fn by_name() {
+        use std::collections::HashMap;
+
+        let a = Cookie::parse("a=1; Path=/").unwrap();
+        let b = Cookie::parse("a=2; Secure").unwrap();
+        assert!(a != b);
+        assert_eq!(a.by_name(), b.by_name());
+
+        let mut map = HashMap::new();
+        for c in vec![a, b.clone()] {
+            map.insert(c.by_name().to_string(), c);
         }
+        assert_eq!(map.len(), 1);
+        assert_eq!(map["a"], b);
+    }
+
+    #[test]
+    fn make_removal() {
+        let c = Cookie::make_removal("foo", Some("/app"), Some("example.com"));
+        assert_eq!(c.name, "foo");
+        assert_eq!(c.value, "");
+        assert_eq!(c.max_age, Some(0));
+        assert_eq!(c.expires, Some(time::at_utc(time::Timespec::new(0, 0))));
+        assert_eq!(c.path, Some("/app".to_string()));
+        assert_eq!(c.domain, Some("example.com".to_string()));
+        assert!(c.is_expired());
+
+        let c = Cookie::make_removal(String::from("foo"), None, None);
+        assert_eq!(c.path, None);
+        assert_eq!(c.domain, None);
+        assert_eq!(c, Cookie::build_removal("foo").finish());
+    }
+
+    #[test]
+    fn sync_expires_from_max_age() {
+        use time;
+
+        let now = time::at_utc(time::Timespec::new(1416772800, 0));
+        let mut cookie = Cookie::parse("foo=bar; Max-Age=3600").unwrap();
+        cookie.sync_expires_from_max_age(now);
+        assert_eq!(cookie.max_age, Some(3600));
+        assert_eq!(cookie.expires.unwrap().to_timespec(),
+                   time::Timespec::new(1416772800 + 3600, 0));
+        assert_eq!(cookie.to_string(),
+                   "foo=bar; Max-Age=3600; Expires=Sun, 23 Nov 2014 21:00:00 GMT");
+
+        // An existing `expires` is kept.
+        let later = now + time::Duration::days(1);
+        cookie.sync_expires_from_max_age(later);
+        assert_eq!(cookie.expires.unwrap().to_timespec().sec, 1416772800 + 3600);
 
-        for (k, v) in self.custom.iter() {
-            try!(write!(f, "; {}", AttrVal(&k, &v)));
+        let mut session = Cookie::parse("foo=bar").unwrap();
+        session.sync_expires_from_max_age(now);
+        assert!(session.is_session());
+    }
+
+    #[test]
+    fn is_simple_pair() {
+        let c = Cookie::parse(" foo = bar ").unwrap();
+        assert!(c.is_simple_pair());
+        assert_eq!(c.to_string(), "foo=bar");
+        assert!(Cookie::parse("foo=bar;").unwrap().is_simple_pair());
+
+        for header in &["foo=bar; Secure", "foo=bar; HttpOnly", "foo=bar; Path=/",
+                        "foo=bar; Domain=example.com", "foo=bar; Max-Age=1",
+                        "foo=bar; Expires=Wed, 21 Oct 2015 07:28:00 GMT",
+                        "foo=bar; SameSite=Lax", "foo=bar; Priority=High",
+                        "foo=bar; x=1", "foo=bar; Partitioned"] {
+            assert!(!Cookie::parse(header).unwrap().is_simple_pair(), "{}", header);
         }
-        Ok(())
     }
-}
 
-impl FromStr for Cookie {
-    type Err = Error;
-    fn from_str(s: &str) -> Result<Cookie, Error> {
-        Cookie::parse(s)
+    #[test]
+    fn downgrade_to_session() {
+        let mut cookie = Cookie::parse("foo=bar; Max-Age=60; \
+                                        Expires=Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert!(!cookie.is_session());
+        cookie.downgrade_to_session();
+        assert_eq!(cookie.expires, None);
+        assert_eq!(cookie.max_age, None);
+        assert!(cookie.is_session());
+        assert!(!cookie.is_expired());
+
+        cookie.max_age = Some(0);
+        assert!(!cookie.is_session());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::Cookie;
+    #[test]
+    fn validate_prefix() {
+        use super::Error;
+
+        let c = Cookie::parse("id=1").unwrap();
+        assert!(c.is_valid_prefix());
+
+        let mut c = Cookie::parse("__Secure-id=1").unwrap();
+        assert_eq!(c.validate_prefix(), Err(Error::PrefixMismatch));
+        c.secure = true;
+        assert_eq!(c.validate_prefix(), Ok(()));
+        c.domain = Some("example.com".to_string());
+        c.path = Some("/app".to_string());
+        assert!(c.is_valid_prefix());
+
+        let host = Cookie::parse("__Host-id=1; Secure; Path=/").unwrap();
+        assert!(host.is_valid_prefix());
+
+        let mut c = host.clone();
+        c.secure = false;
+        assert_eq!(c.validate_prefix(), Err(Error::PrefixMismatch));
+
+        let mut c = host.clone();
+        c.path = None;
+        assert!(!c.is_valid_prefix());
+        c.path = Some("/app".to_string());
+        assert!(!c.is_valid_prefix());
+
+        let mut c = host.clone();
+        c.domain = Some("example.com".to_string());
+        assert!(!c.is_valid_prefix());
+
+        // Prefixes are case-sensitive.
+        assert!(Cookie::parse("__host-id=1").unwrap().is_valid_prefix());
+    }
 
     #[test]
-    fn parse() {
-        assert!(Cookie::parse("bar").is_err());
-        assert!(Cookie::parse("=bar").is_err());
-        assert!(Cookie::parse(" =bar").is_err());
-        assert!(Cookie::parse("foo=").is_ok());
-        let mut expected = Cookie::new("foo".to_string(), "bar".to_string());
-        assert_eq!(Cookie::parse("foo=bar").ok().unwrap(), expected);
-        assert_eq!(Cookie::parse("foo = bar").ok().unwrap(), expected);
-        assert_eq!(Cookie::parse(" foo=bar ").ok().unwrap(), expected);
-        assert_eq!(Cookie::parse(" foo=bar ;Domain=").ok().unwrap(), expected);
-        assert_eq!(Cookie::parse(" foo=bar ;Domain= ").ok().unwrap(), expected);
-        assert_eq!(Cookie::parse(" foo=bar ;Ignored").ok().unwrap(), expected);
-        expected.httponly = true;
-        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly").ok().unwrap(), expected);
-        assert_eq!(Cookie::parse(" foo=bar ;httponly").ok().unwrap(), expected);
-        assert_eq!(Cookie::parse(" foo=bar ;HTTPONLY=whatever").ok().unwrap(), expected);
-        assert_eq!(Cookie::parse(" foo=bar ; sekure; HTTPONLY").ok().unwrap(), expected);
-        expected.secure = true;
-        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure").ok().unwrap(), expected);
-        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure=aaaa").ok().unwrap(), expected);
-        expected.max_age = Some(0);
-        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
-                                  Max-Age=0").ok().unwrap(), expected);
-        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
-                                  Max-Age = 0 ").ok().unwrap(), expected);
-        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
-                                  Max-Age=-1").ok().unwrap(), expected);
-        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
-                                  Max-Age = -1 ").ok().unwrap(), expected);
-        expected.max_age = Some(4);
-        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
-                                  Max-Age=4").ok().unwrap(), expected);
-        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
-                                  Max-Age = 4 ").ok().unwrap(), expected);
-        expected.path = Some("/foo".to_string());
-        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
-                                  Max-Age=4; Path=/foo").ok().unwrap(), expected);
-        expected.domain = Some("foo.com".to_string());
-        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
-                                  Max-Age=4; Path=/foo; \
-                                  Domain=foo.com").ok().unwrap(), expected);
-        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
-                                  Max-Age=4; Path=/foo; \
-                                  Domain=FOO.COM").ok().unwrap(), expected);
-        expected.custom.insert("wut".to_string(), "lol".to_string());
-        assert_eq!(Cookie::parse(" foo=bar ;HttpOnly; Secure; \
-                                  Max-Age=4; Path=/foo; \
-                                  Domain=foo.com; wut=lol").ok().unwrap(), expected);
+    fn make_host_only() {
+        use url::Url;
 
-        assert_eq!(expected.to_string(),
-                   "foo=bar; HttpOnly; Secure; Path=/foo; Domain=foo.com; \
-                    Max-Age=4; wut=lol");
+        let mut cookie = Cookie::parse("foo=bar; Domain=example.com; Path=/").unwrap();
+        assert!(cookie.matches_domain("www.example.com"));
+        cookie.make_host_only();
+        assert_eq!(cookie.domain, None);
+        assert_eq!(cookie.path, Some("/".to_string()));
+        assert_eq!(cookie.to_string(), "foo=bar; Path=/");
+
+        // Set by example.com, the cookie is now sent back to that host but is
+        // no longer domain-matched by its subdomains.
+        assert!(cookie.is_host_only());
+        assert!(cookie.matches(&Url::parse("http://example.com/").unwrap()));
+        assert!(!cookie.matches_domain("www.example.com"));
+        assert!(!cookie.matches_domain("example.com"));
     }
 
     #[test]
-    fn cookie_parse_error() {
-        match Cookie::parse("bar") {
-            Ok(_) => assert!(false),
-            Err(_) => assert!(true),
+    fn scope_to_path() {
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        cookie.scope_to_path("/admin");
+        assert_eq!(cookie.path, Some("/admin".to_string()));
+        cookie.scope_to_path(String::from("relative"));
+        assert_eq!(cookie.path, Some("relative".to_string()));
+
+        assert!(cookie.try_scope_to_path("/app").is_ok());
+        assert_eq!(cookie.path, Some("/app".to_string()));
+        assert!(cookie.try_scope_to_path("app").is_err());
+        assert!(cookie.try_scope_to_path("").is_err());
+        assert_eq!(cookie.path, Some("/app".to_string()));
+        assert_eq!(cookie.to_string(), "foo=bar; Path=/app");
+    }
+
+    #[test]
+    fn attribute_pairs() {
+        fn pair(k: &str, v: &str) -> (String, String) {
+            (k.to_string(), v.to_string())
         }
+
+        let cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        assert!(cookie.attribute_pairs().is_empty());
+
+        let cookie = Cookie::parse("foo=bar; wut=lol; Domain=foo.com; Max-Age=4; \
+                                    Path=/foo; Secure; HttpOnly; \
+                                    Expires=Sun, 23 Nov 2014 20:00:00 GMT").unwrap();
+        assert_eq!(cookie.attribute_pairs(),
+                   vec![pair("HttpOnly", ""),
+                        pair("Secure", ""),
+                        pair("Path", "/foo"),
+                        pair("Domain", "foo.com"),
+                        pair("Max-Age", "4"),
+                        pair("Expires", "Sun, 23 Nov 2014 20:00:00 GMT"),
+                        pair("wut", "lol")]);
     }
 
     #[test]
-    fn odd_characters() {
-        let expected = Cookie::new("foo".to_string(), "b%2Fr".to_string());
-        assert_eq!(Cookie::parse("foo=b%2Fr").ok().unwrap(), expected);
+    fn pair_len() {
+        use std::iter;
+
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        assert_eq!(cookie.pair_len(), 7);
+        assert!(!cookie.pair_too_large());
+
+        cookie.value = iter::repeat('a').take(4092).collect();
+        assert_eq!(cookie.pair_len(), 4096);
+        assert!(!cookie.pair_too_large());
+
+        cookie.value.push('a');
+        assert!(cookie.pair_too_large());
+    }
+
+    #[test]
+    fn fits_in() {
+        use std::iter;
+
+        let mut cookie = Cookie::parse("foo=bar; Path=/").unwrap();
+        assert_eq!(cookie.header_len(), 15);
+        assert!(cookie.fits_in(15));
+        assert!(!cookie.fits_in(14));
+        assert!(cookie.to_string_checked_within(15).is_ok());
+        assert!(cookie.to_string_checked_within(14).is_err());
+
+        cookie.custom.insert("huge".to_string(), iter::repeat('a').take(4096).collect());
+        assert!(!cookie.pair_too_large());
+        assert!(!cookie.fits_in(4096));
+        assert!(cookie.to_string_checked_within(4096).is_err());
+        assert!(cookie.to_string_checked().is_ok());
+    }
+
+    #[test]
+    fn flag_attrs() {
+        let cookie = Cookie::parse("foo=bar; SomeFlag; x=1").unwrap();
+        assert_eq!(cookie.flag_attrs, vec!["SomeFlag".to_string()]);
+        assert_eq!(cookie.custom.get("x"), Some(&"1".to_string()));
+        assert_eq!(cookie.to_string(), "foo=bar; SomeFlag; x=1");
+        assert_eq!(Cookie::parse(&cookie.to_string()).unwrap(), cookie);
+
+        let cookie = Cookie::parse("foo=bar; Partitioned; Secure; Path; Max-Age; ;").unwrap();
+        assert_eq!(cookie.flag_attrs, vec!["Partitioned".to_string()]);
+        assert_eq!(cookie.to_string(), "foo=bar; Secure; Partitioned");
+    }
+
+    #[test]
+    fn sort_custom_by() {
+        let mut cookie = Cookie::parse("foo=bar; b=2; HttpOnly; a=1; c=3").unwrap();
+        assert_eq!(cookie.to_string(), "foo=bar; HttpOnly; b=2; a=1; c=3");
+
+        cookie.sort_custom_by(|a, b| b.cmp(a));
+        assert_eq!(cookie.to_string(), "foo=bar; HttpOnly; c=3; b=2; a=1");
+
+        cookie.sort_custom_by(|a, b| a.cmp(b));
+        assert_eq!(cookie.to_string(), "foo=bar; HttpOnly; a=1; b=2; c=3");
+    }
+
+    #[test]
+    fn canonicalize_custom_keys() {
+        let mut cookie = Cookie::parse("foo=bar; Foo=1; Bar=a; foo=2; Secure").unwrap();
+        assert_eq!(cookie.custom.len(), 3);
+
+        cookie.canonicalize_custom_keys();
+        assert_eq!(cookie.custom.len(), 2);
+        assert_eq!(cookie.custom.get("foo"), Some(&"2".to_string()));
+        assert_eq!(cookie.custom.get("bar"), Some(&"a".to_string()));
+        assert_eq!(cookie.to_string(), "foo=bar; Secure; foo=2; bar=a");
+    }
+
+    #[test]
+    fn escape_value() {
+        let mut cookie = Cookie::new("foo".to_string(), "hello world".to_string());
+        assert!(cookie.escape_value());
+        assert_eq!(cookie.value, "hello%20world");
+        assert_eq!(Cookie::parse_encoded(&cookie.to_string()).unwrap().value,
+                   "hello world");
+
+        let mut cookie = Cookie::new("foo".to_string(), "a;b\"c".to_string());
+        assert!(cookie.escape_value());
+        assert_eq!(cookie.value, "a%3Bb%22c");
+
+        let mut cookie = Cookie::new("foo".to_string(), "clean-value_1".to_string());
+        assert!(!cookie.escape_value());
+        assert_eq!(cookie.value, "clean-value_1");
     }
 
     #[test]
@@ -361,16 +3963,39 @@ mod tests {
         assert_eq!(cookie.pair().to_string(), "foo=bar".to_string());
     }
 
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn expires_chrono() {
+        use chrono::{TimeZone, Utc};
+        use time;
+
+        let mut cookie = Cookie::new("foo".to_string(), "bar".to_string());
+        assert_eq!(cookie.expires_chrono(), None);
+
+        let expires = Utc.timestamp_opt(1416772800, 123456789).unwrap();
+        cookie.set_expires_chrono(expires);
+        assert_eq!(cookie.expires.unwrap().to_timespec(),
+                   time::Timespec::new(1416772800, 123456789));
+        assert_eq!(cookie.expires_chrono(), Some(expires));
+        assert_eq!(cookie.to_string(), "foo=bar; Expires=Sun, 23 Nov 2014 20:00:00 GMT");
+
+        let before_epoch = Utc.timestamp_opt(-86401, 5).unwrap();
+        cookie.set_expires_chrono(before_epoch);
+        assert_eq!(cookie.expires_chrono(), Some(before_epoch));
+
+        let parsed = Cookie::parse("foo=bar; Expires=Sun, 23 Nov 2014 20:00:00 GMT").unwrap();
+        assert_eq!(parsed.expires_chrono(), Some(Utc.timestamp_opt(1416772800, 0).unwrap()));
+    }
+
     #[cfg(feature = "serialize-serde")]
     #[test]
     fn test_serialize() {
         #[cfg(feature = "serialize-serde")] extern crate serde_json;
 
-        use super::Cookie;
+        use super::{Cookie, CustomAttributes};
         use time;
-        use std::collections::BTreeMap;
 
-        let mut custom = BTreeMap::new();
+        let mut custom = CustomAttributes::new();
         custom.insert("x86".to_string(), "rdi".to_string());
         custom.insert("arm".to_string(), "x0".to_string());
         let original = Cookie {
@@ -383,7 +4008,10 @@ mod tests {
             path: Some("/".to_owned()),
             secure: true,
             httponly: false,
-            custom: custom
+            same_site: None,
+            priority: None,
+            custom: custom,
+            flag_attrs: vec!["Partitioned".to_string()],
         };
 
         let serialized = serde_json::to_string(&original).unwrap();
@@ -393,16 +4021,112 @@ mod tests {
         assert_eq!(original, roundtrip);
     }
 
+    #[cfg(feature = "serialize-serde")]
+    #[test]
+    fn structured_serde() {
+        extern crate serde_json;
+
+        use super::{Cookie, StructuredCookie};
+        use time;
+
+        let mut c = Cookie::parse("foo=bar; Path=/; Domain=example.com; Max-Age=60; \
+                                   Expires=Sun, 23 Nov 2014 20:00:00 GMT; HttpOnly; \
+                                   SameSite=Strict; Priority=Low; Partitioned").unwrap();
+        c.custom.insert("x".to_string(), "a;b".to_string());
+        assert!(Cookie::parse(&c.to_string()).unwrap() != c);
+
+        let json = c.to_json();
+        assert_eq!(json.find("path").and_then(|v| v.as_str()), Some("/"));
+        assert_eq!(json.lookup("expires.secs").and_then(|v| v.as_i64()), Some(1416772800));
+        assert_eq!(json.lookup("expires.nsecs").and_then(|v| v.as_u64()), Some(0));
+        assert_eq!(json.find("same_site").and_then(|v| v.as_str()), Some("Strict"));
+        assert_eq!(json.lookup("custom.x").and_then(|v| v.as_str()), Some("a;b"));
+        assert_eq!(json.find("flag_attrs").and_then(|v| v.as_array()).map(|a| a.len()),
+                   Some(1));
+
+        let s = serde_json::to_string(&StructuredCookie(c.clone())).unwrap();
+        let StructuredCookie(mut roundtrip) = serde_json::from_str(&s).unwrap();
+        // A parsed date leaves `tm_yday` unset, so only compare the instant.
+        assert_eq!(roundtrip.expires.map(|t| t.to_timespec()),
+                   c.expires.map(|t| t.to_timespec()));
+        roundtrip.expires = c.expires;
+        assert_eq!(roundtrip, c);
+
+        c.expires = Some(time::at_utc(time::Timespec::new(-1, 999999999)));
+        let json = c.to_json();
+        assert_eq!(json.lookup("expires.secs").and_then(|v| v.as_i64()), Some(-1));
+        assert_eq!(json.lookup("expires.nsecs").and_then(|v| v.as_u64()), Some(999999999));
+        assert_eq!(Cookie::from_json(&json).unwrap(), c);
+
+        // The string form is still what `Cookie` itself serializes to.
+        let s = serde_json::to_string(&Cookie::parse("a=1; Secure").unwrap()).unwrap();
+        assert_eq!(s, r#""a=1; Secure""#);
+
+        assert!(serde_json::from_str::<StructuredCookie>(r#"{"name": "a"}"#).is_err());
+        assert!(serde_json::from_str::<StructuredCookie>(r#""a=1""#).is_err());
+    }
+
+    #[cfg(feature = "serialize-serde")]
+    #[test]
+    fn from_json() {
+        use super::{SameSite, serde_json};
+
+        let json = r#"{
+            "name": "foo",
+            "value": "bar",
+            "expires": "Sun, 23 Nov 2014 20:00:00 GMT",
+            "max_age": 42,
+            "domain": "example.com",
+            "path": null,
+            "httponly": true,
+            "same_site": "lax",
+            "custom": {"wut": "lol"},
+            "comment": "ignored"
+        }"#;
+        let value: serde_json::Value = serde_json::from_str(json).unwrap();
+        let cookie = Cookie::from_json(&value).unwrap();
+        assert_eq!(cookie.name, "foo");
+        assert_eq!(cookie.value, "bar");
+        assert_eq!(cookie.max_age, Some(42));
+        assert_eq!(cookie.domain, Some("example.com".to_string()));
+        assert_eq!(cookie.path, None);
+        assert!(cookie.httponly);
+        assert!(!cookie.secure);
+        assert_eq!(cookie.same_site, Some(SameSite::Lax));
+        assert_eq!(cookie.custom.get("wut"), Some(&"lol".to_string()));
+        assert_eq!(cookie.to_string(),
+                   "foo=bar; HttpOnly; SameSite=Lax; Domain=example.com; \
+                    Max-Age=42; Expires=Sun, 23 Nov 2014 20:00:00 GMT; wut=lol");
+
+        let json = r#"{"name": "foo", "value": "bar", "expires": {"secs": 1416772800}}"#;
+        let value: serde_json::Value = serde_json::from_str(json).unwrap();
+        let expires = Cookie::from_json(&value).unwrap().expires.unwrap();
+        assert_eq!(expires.to_timespec(), cookie.expires.unwrap().to_timespec());
+
+        for bad in &[r#""foo=bar""#,
+                     r#"{"value": "bar"}"#,
+                     r#"{"name": "", "value": "bar"}"#,
+                     r#"{"name": "foo", "value": "bar", "secure": "yes"}"#,
+                     r#"{"name": "foo", "value": "bar", "expires": "soon"}"#,
+                     r#"{"name": "foo", "value": "bar", "expires": {"nsecs": 0}}"#,
+                     r#"{"name": "foo", "value": "bar", "expires": {"secs": 0, "nsecs": -1}}"#,
+                     r#"{"name": "foo", "value": "bar",
+                         "expires": {"secs": 0, "nsecs": 1000000000}}"#,
+                     r#"{"name": "foo", "value": "bar", "same_site": "sometimes"}"#] {
+            let value: serde_json::Value = serde_json::from_str(bad).unwrap();
+            assert!(Cookie::from_json(&value).is_err(), "{}", bad);
+        }
+    }
+
     #[cfg(feature = "serialize-serde")]
     #[test]
     fn test_serialize_odd_characters() {
         #[cfg(feature = "serialize-serde")] extern crate serde_json;
 
-        use super::Cookie;
+        use super::{Cookie, CustomAttributes};
         use time;
-        use std::collections::BTreeMap;
 
-        let mut custom = BTreeMap::new();
+        let mut custom = CustomAttributes::new();
         custom.insert("x86".to_string(), "rdi".to_string());
         custom.insert("arm".to_string(), "x0".to_string());
         let original = Cookie {
@@ -415,7 +4139,10 @@ mod tests {
             path: Some("/".to_owned()),
             secure: true,
             httponly: false,
-            custom: custom
+            same_site: None,
+            priority: None,
+            custom: custom,
+            flag_attrs: vec!["Partitioned".to_string()],
         };
 
         let serialized = serde_json::to_string(&original).unwrap();
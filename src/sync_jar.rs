@@ -0,0 +1,230 @@
+//! A cookie jar which can be shared between threads.
+
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+use time;
+
+use {Cookie, CookieJar, Error};
+use jar::{self, Root};
+
+/// A `CookieJar` which can be shared between threads
+///
+/// `CookieJar` keeps its cookies in `RefCell`s, so it can't be used from
+/// several threads at once. A `SyncCookieJar` keeps the state of a root
+/// `CookieJar` behind a `Mutex` instead and is both `Send` and `Sync`, so it
+/// can be shared with an `Arc` between the handlers of a multi-threaded
+/// server. Every operation is the `CookieJar` one, including its name prefix
+/// checks, defaults and clock.
+///
+/// The common operations are available directly, while `with_jar` gives
+/// access to the whole `CookieJar` API, such as the signed, encrypted and
+/// permanent child jars.
+///
+/// There is a single lock for the whole jar. Each method takes it and
+/// releases it before returning, and `with_jar` holds it while its closure
+/// runs, so operations on different cookies still wait for each other. Reads
+/// return copies of the stored cookies, so no lock is held between calls.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::thread;
+/// use cookie::{Cookie, SyncCookieJar};
+///
+/// let jar = Arc::new(SyncCookieJar::new(b"f8f9eaf1ecdedff5e5b749c58115441e"));
+/// let handle = {
+///     let jar = jar.clone();
+///     thread::spawn(move || {
+///         jar.with_jar(|j| j.permanent().add(Cookie::new("key".to_string(),
+///                                                         "value".to_string())))
+///     })
+/// };
+/// handle.join().unwrap();
+/// assert_eq!(jar.get("key").unwrap().value, "value");
+/// assert!(jar.get("key").unwrap().expires.is_some());
+/// ```
+pub struct SyncCookieJar {
+    root: Mutex<Root>,
+}
+
+impl SyncCookieJar {
+    /// Creates a new empty cookie jar with the given signing key.
+    ///
+    /// See `CookieJar::new`.
+    pub fn new(key: &[u8]) -> SyncCookieJar {
+        SyncCookieJar::from_jar(CookieJar::new(key))
+    }
+
+    /// Creates a new empty cookie jar with the given signing key, which reads
+    /// the current time from `clock`.
+    ///
+    /// See `CookieJar::with_clock`.
+    pub fn with_clock(key: &[u8], clock: Box<Fn() -> time::Tm + Send>) -> SyncCookieJar {
+        SyncCookieJar::from_jar(CookieJar::with_clock(key, clock))
+    }
+
+    /// Creates a new empty cookie jar which signs and encrypts with `key`,
+    /// but also accepts cookies signed or encrypted with any of `old_keys`.
+    ///
+    /// See `CookieJar::with_keys`.
+    pub fn with_keys(key: &[u8], old_keys: &[&[u8]]) -> SyncCookieJar {
+        SyncCookieJar::from_jar(CookieJar::with_keys(key, old_keys))
+    }
+
+    fn from_jar(jar: CookieJar<'static>) -> SyncCookieJar {
+        SyncCookieJar { root: Mutex::new(jar::into_root(jar)) }
+    }
+
+    // Each `CookieJar` method leaves the jar consistent even if a later one
+    // panics, so a panic while the lock was held doesn't need to be passed
+    // on to every other thread.
+    fn lock(&self) -> MutexGuard<Root> {
+        self.root.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Runs `f` with the jar locked, returning its result.
+    ///
+    /// This gives access to every `CookieJar` method, including the child
+    /// jars such as `signed` and `encrypted`. Other threads wait for the lock
+    /// until `f` returns, so keep it short.
+    pub fn with_jar<F, R>(&self, f: F) -> R
+        where F: FnOnce(&CookieJar) -> R
+    {
+        jar::with_root(&self.lock(), |j| f(j))
+    }
+
+    /// Adds an original cookie from a request.
+    ///
+    /// See `CookieJar::add_original`.
+    pub fn add_original(&self, cookie: Cookie) {
+        jar::with_root(&self.lock(), |j| j.add_original(cookie));
+    }
+
+    /// Adds a new cookie to this jar.
+    ///
+    /// See `CookieJar::add`.
+    pub fn add(&self, cookie: Cookie) {
+        self.with_jar(|j| j.add(cookie));
+    }
+
+    /// Adds a new cookie to this jar, enforcing cookie name prefixes.
+    ///
+    /// See `CookieJar::try_add`.
+    pub fn try_add(&self, cookie: Cookie) -> Result<(), Error> {
+        self.with_jar(|j| j.try_add(cookie))
+    }
+
+    /// Removes a cookie from this jar.
+    pub fn remove(&self, name: &str) {
+        self.with_jar(|j| j.remove(name));
+    }
+
+    /// Returns a copy of the cookie named `name`.
+    ///
+    /// See `CookieJar::get`.
+    pub fn get(&self, name: &str) -> Option<Cookie> {
+        self.with_jar(|j| j.get(name))
+    }
+
+    /// Returns whether this jar holds a cookie named `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.with_jar(|j| j.contains(name))
+    }
+
+    /// Returns a copy of every cookie in this jar, sorted by name.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        self.with_jar(|j| j.iter().collect())
+    }
+
+    /// Calculates the changes that have occurred to this jar, returning a
+    /// vector of `Set-Cookie` headers.
+    ///
+    /// See `CookieJar::delta`.
+    pub fn delta(&self) -> Vec<Cookie> {
+        self.with_jar(|j| j.delta())
+    }
+}
+
+impl fmt::Debug for SyncCookieJar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.with_jar(|j| fmt::Debug::fmt(j, f))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+    use time;
+
+    use {Cookie, CookieJar};
+    use super::SyncCookieJar;
+
+    const KEY: &'static [u8] = b"f8f9eaf1ecdedff5e5b749c58115441e";
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SyncCookieJar>();
+    }
+
+    #[test]
+    fn same_as_cookie_jar() {
+        let epoch = time::at_utc(time::Timespec::new(0, 0));
+        let jar = SyncCookieJar::with_clock(KEY, Box::new(move || epoch));
+        let mut plain = CookieJar::with_clock(KEY, Box::new(move || epoch));
+
+        jar.add_original(Cookie::parse("old=1; Path=/app; Secure").unwrap());
+        jar.with_jar(|j| j.set_default(Cookie::parse("t=; HttpOnly").unwrap()));
+        jar.add(Cookie::new("a".to_string(), "1".to_string()));
+        jar.add(Cookie::parse("__Host-id=1; Domain=example.com").unwrap());
+        jar.remove("old");
+
+        plain.add_original(Cookie::parse("old=1; Path=/app; Secure").unwrap());
+        plain.set_default(Cookie::parse("t=; HttpOnly").unwrap());
+        plain.add(Cookie::new("a".to_string(), "1".to_string()));
+        plain.add(Cookie::parse("__Host-id=1; Domain=example.com").unwrap());
+        plain.remove("old");
+
+        assert_eq!(jar.delta(), plain.delta());
+        assert!(jar.get("a").unwrap().httponly);
+        assert!(!jar.contains("__Host-id"));
+        assert!(!jar.contains("old"));
+        assert!(jar.try_add(Cookie::parse("__Host-x=1; Path=/a").unwrap()).is_err());
+        assert_eq!(jar.cookies().len(), 1);
+    }
+
+    #[cfg(feature = "secure")]
+    #[test]
+    fn signed() {
+        let jar = SyncCookieJar::new(KEY);
+        jar.with_jar(|j| j.signed().add(Cookie::new("a".to_string(), "value".to_string())));
+        assert!(jar.get("a").unwrap().value != "value");
+        assert_eq!(jar.with_jar(|j| j.signed().find("a")).unwrap().value, "value");
+    }
+
+    #[test]
+    fn threads() {
+        let jar = Arc::new(SyncCookieJar::new(KEY));
+        let handles = (0..8).map(|i| {
+            let jar = jar.clone();
+            thread::spawn(move || {
+                for j in 0..50 {
+                    let name = format!("{}-{}", i, j);
+                    jar.add(Cookie::new(name.clone(), "v".to_string()));
+                    assert!(jar.get(&name).is_some());
+                    if j % 2 == 0 {
+                        jar.remove(&name);
+                    }
+                }
+            })
+        }).collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(jar.cookies().len(), 8 * 25);
+        assert_eq!(jar.delta().len(), 8 * 50);
+    }
+}